@@ -0,0 +1,1112 @@
+//! Assembler for CHIP-8 programs written in Octo syntax, e.g.
+//!
+//! ```text
+//! :const SPEED 3
+//! :alias x v1
+//!
+//! : main
+//!     i := ball
+//!     loop-forever
+//!
+//! : loop-forever
+//!     x += SPEED
+//!     sprite x x 1
+//!     jump loop-forever
+//!
+//! : ball
+//!     0b10000000
+//! ```
+//!
+//! Numbers on their own are emitted as data bytes and a label on its own calls
+//! it. Labels may be used before they are defined, constants and aliases can't.
+//!
+//! Octo's structured control flow is supported: `if ... then`, `if ... begin`
+//! with an optional `else` and `end`, and `loop ... again` with any number of
+//! `while` conditions. Comparisons with `<`, `>`, `<=` and `>=` use `vf`.
+//! `:macro NAME ARGS { ... }` defines a macro, `:calc NAME { ... }` a constant
+//! computed from an expression and `:breakpoint NAME` marks an address for the debugger.
+
+use crate::cpu::PROGRAM_START;
+use crate::instruction::Instruction;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Guards against files including each other forever
+const MAX_INCLUDES: usize = 256;
+
+/// Guards against macros expanding themselves forever
+const MAX_EXPANSIONS: usize = 65536;
+
+/// Register clobbered by `<`, `>`, `<=` and `>=` comparisons
+const COMPARE_TEMP: u8 = 0xF;
+
+/// An error in the source, pointing at the file and line it was found on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+/// An assembled program, loaded at the program start
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    /// Address of every label, e.g. for setting breakpoints by name
+    pub labels: BTreeMap<String, u16>,
+    /// Addresses marked with `:breakpoint`, with their names
+    pub breakpoints: BTreeMap<u16, String>,
+}
+
+/// Assemble source text. Included files are found relative to the working directory
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    Assembler::new(tokenize(source, &Rc::new(PathBuf::from("<input>")))).run()
+}
+
+/// Assemble a source file. Included files are found relative to the including file
+pub fn assemble_file(path: &Path) -> Result<Program, AssembleError> {
+    let source = fs::read_to_string(path).map_err(|e| AssembleError {
+        file: path.display().to_string(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    Assembler::new(tokenize(&source, &Rc::new(path.to_path_buf()))).run()
+}
+
+/// Read a ROM, or assemble it first if it is Octo source ending in `.8o`
+pub fn load_rom(path: &Path) -> Result<Program, Box<dyn Error>> {
+    if path.extension().map_or(false, |extension| extension == "8o") {
+        Ok(assemble_file(path)?)
+    }
+    else {
+        Ok(Program { rom: fs::read(path)?, ..Program::default() })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    /// Whether the token was written in quotes, so it can't be an instruction or number
+    quoted: bool,
+    file: Rc<PathBuf>,
+    line: usize,
+}
+
+/// Split source into whitespace separated tokens, dropping `#` comments
+fn tokenize(source: &str, file: &Rc<PathBuf>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            }
+            else if c == '#' {
+                break;
+            }
+            else if c == '"' {
+                chars.next();
+                let text: String = chars.by_ref().take_while(|c| *c != '"').collect();
+                tokens.push(Token { text, quoted: true, file: file.clone(), line: index + 1 });
+            }
+            else {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token { text, quoted: false, file: file.clone(), line: index + 1 });
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Parse a decimal, `0x` hex or `0b` binary number, optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Parse `v0` through `vf`
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Where an address has to be patched in once its label is known
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FixupKind {
+    /// The low 12 bits of the opcode at the address
+    Nnn,
+    /// The two bytes at the address
+    Long,
+}
+
+struct Fixup {
+    address: u16,
+    kind: FixupKind,
+    token: Token,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A block of structured control flow which hasn't been closed yet
+enum Block {
+    /// `loop`, with the address `again` jumps back to and the jumps of each `while` out of it
+    Loop { token: Token, start: u16, exits: Vec<u16> },
+    /// `if ... begin`, with the jump taken when the condition is false
+    If { token: Token, jump: u16, has_else: bool },
+}
+
+/// A parsed `if` or `while` condition
+struct Condition {
+    /// Instructions comparing into `vf` before the skip
+    setup: Vec<Instruction>,
+    /// Skips the next instruction when the condition is false
+    skip_unless: Instruction,
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    position: usize,
+    includes: usize,
+    /// Memory image starting at the program start
+    rom: Vec<u8>,
+    here: u32,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    fixups: Vec<Fixup>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    blocks: Vec<Block>,
+    breakpoints: BTreeMap<u16, String>,
+}
+
+impl Assembler {
+
+    fn new(tokens: Vec<Token>) -> Self {
+        Assembler {
+            tokens,
+            position: 0,
+            includes: 0,
+            rom: Vec::new(),
+            here: PROGRAM_START as u32,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            fixups: Vec::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            blocks: Vec::new(),
+            breakpoints: BTreeMap::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<Program, AssembleError> {
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+        match self.blocks.last() {
+            Some(Block::Loop { token, .. }) => return Err(error_at(token, "loop without again".to_string())),
+            Some(Block::If { token, .. }) => return Err(error_at(token, "begin without end".to_string())),
+            None => {}
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            // Only labels may be used before they are defined
+            let address = match self.labels.get(&fixup.token.text) {
+                Some(address) => *address as i64,
+                None if self.constants.contains_key(&fixup.token.text) => {
+                    return Err(error_at(&fixup.token, format!("constant {} is used before it is defined", fixup.token.text)));
+                }
+                None => return Err(error_at(&fixup.token, format!("undefined name {}", fixup.token.text))),
+            };
+            let offset = (fixup.address - PROGRAM_START) as usize;
+            match fixup.kind {
+                FixupKind::Nnn => {
+                    if !(0..=0xfff).contains(&address) {
+                        return Err(error_at(&fixup.token, format!("{} is out of range for a 12 bit address", fixup.token.text)));
+                    }
+                    self.rom[offset] |= (address >> 8) as u8;
+                    self.rom[offset + 1] = address as u8;
+                }
+                FixupKind::Long => {
+                    self.rom[offset] = (address >> 8) as u8;
+                    self.rom[offset + 1] = address as u8;
+                }
+            }
+        }
+
+        Ok(Program { rom: self.rom, labels: self.labels, breakpoints: self.breakpoints })
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token.quoted {
+            return Err(error_at(&token, format!("unexpected string \"{}\"", token.text)));
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                let address = self.here_address(&name)?;
+                if self.labels.insert(name.text.clone(), address).is_some() {
+                    return Err(error_at(&name, format!("label {} is already defined", name.text)));
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":include" => self.include()?,
+            ":org" => {
+                let value = self.value()?;
+                if !(PROGRAM_START as i64..=0xffff).contains(&value) {
+                    return Err(error_at(&token, format!(":org address {:#x} is outside the program space", value)));
+                }
+                self.here = value as u32;
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    let expression = self.braces()?;
+                    byte_value(self.calc(&expression, &token)?.floor() as i64, &token)?
+                } else {
+                    self.byte()?
+                };
+                self.emit_byte(value, &token)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let expression = self.braces()?;
+                let value = self.calc(&expression, &name)?;
+                self.constants.insert(name.text, value.floor() as i64);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut parameters = Vec::new();
+                while !self.peek_is("{") {
+                    parameters.push(self.name()?.text);
+                }
+                let body = self.braces()?;
+                self.macros.insert(name.text, Macro { parameters, body });
+            }
+            ":breakpoint" => {
+                let name = self.next()?;
+                let address = self.here_address(&name)?;
+                self.breakpoints.insert(address, name.text);
+            }
+            ":call" => self.address_instruction(Instruction::Call(0), &token)?,
+            "clear" => self.emit(Instruction::Clear, &token)?,
+            "return" | ";" => self.emit(Instruction::Return, &token)?,
+            "exit" => self.emit(Instruction::Exit, &token)?,
+            "lores" => self.emit(Instruction::Lores, &token)?,
+            "hires" => self.emit(Instruction::Hires, &token)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollDown(n), &token)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollUp(n), &token)?;
+            }
+            "scroll-left" => self.emit(Instruction::ScrollLeft, &token)?,
+            "scroll-right" => self.emit(Instruction::ScrollRight, &token)?,
+            "audio" => self.emit(Instruction::LoadAudioPattern, &token)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::SelectPlanes(n), &token)?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::StoreBcd(x), &token)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if token.text == "save" { Instruction::SaveRange(x, y) } else { Instruction::LoadRange(x, y) }
+                } else if token.text == "save" {
+                    Instruction::Store(x)
+                } else {
+                    Instruction::Load(x)
+                };
+                self.emit(instruction, &token)?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::SaveFlags(x), &token)?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LoadFlags(x), &token)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Instruction::Draw(x, y, n), &token)?;
+            }
+            "jump" => self.address_instruction(Instruction::Jump(0), &token)?,
+            "jump0" => self.address_instruction(Instruction::JumpOffset(0), &token)?,
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => {
+                        self.emit_condition(&condition, false, &token)?;
+                    }
+                    "begin" => {
+                        let jump = self.emit_condition(&condition, true, &token)?;
+                        self.blocks.push(Block::If { token, jump, has_else: false });
+                    }
+                    _ => return Err(error_at(&keyword, format!("expected then or begin, found {}", keyword.text))),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { token: begin, jump, has_else: false }) => {
+                    let end_jump = self.here as u16;
+                    self.emit(Instruction::Jump(0), &token)?;
+                    self.patch_jump(jump, &token)?;
+                    self.blocks.push(Block::If { token: begin, jump: end_jump, has_else: true });
+                }
+                _ => return Err(error_at(&token, "else without if ... begin".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => self.patch_jump(jump, &token)?,
+                _ => return Err(error_at(&token, "end without if ... begin".to_string())),
+            },
+            "loop" => {
+                let start = self.here as u16;
+                self.blocks.push(Block::Loop { token, start, exits: Vec::new() });
+            }
+            "while" => {
+                let condition = self.condition()?;
+                let jump = self.emit_condition(&condition, true, &token)?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => exits.push(jump),
+                    _ => return Err(error_at(&token, "while outside of loop".to_string())),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.emit(Instruction::Jump(start), &token)?;
+                    for exit in exits {
+                        self.patch_jump(exit, &token)?;
+                    }
+                }
+                _ => return Err(error_at(&token, "again without loop".to_string())),
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::SetDelay(x),
+                    "buzzer" => Instruction::SetSound(x),
+                    _ => Instruction::SetPitch(x),
+                };
+                self.emit(instruction, &token)?;
+            }
+            "i" => self.index_statement(&token)?,
+            _ => {
+                if self.macros.contains_key(&token.text) {
+                    self.expand_macro(&token)?;
+                }
+                else if let Some(x) = self.lookup_register(&token.text) {
+                    self.register_statement(x, &token)?;
+                }
+                else if let Some(value) = self.lookup_value(&token) {
+                    self.emit_byte(byte_value(value, &token)?, &token)?;
+                }
+                else if is_name(&token.text) {
+                    // A label on its own calls it, it may not be defined yet
+                    self.fixups.push(Fixup { address: self.here as u16, kind: FixupKind::Nnn, token: token.clone() });
+                    self.emit(Instruction::Call(0), &token)?;
+                }
+                else {
+                    return Err(error_at(&token, format!("unknown instruction {}", token.text)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `i := nnn`, `i := long nnnn`, `i := hex vx`, `i := bighex vx` and `i += vx`
+    fn index_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            ":=" if self.peek_is("hex") || self.peek_is("bighex") => {
+                let big = self.next()?.text == "bighex";
+                let x = self.register()?;
+                self.emit(if big { Instruction::LoadBigFont(x) } else { Instruction::LoadFont(x) }, token)
+            }
+            ":=" if self.peek_is("long") => {
+                self.next()?;
+                self.emit(Instruction::LoadLongIndex, token)?;
+                // The address follows the opcode, which may have filled memory
+                let address = u16::try_from(self.here).map_err(|_| error_at(token, "program is larger than 64 KiB".to_string()))?;
+                let operand = self.next()?;
+                let value = match self.lookup_value(&operand) {
+                    Some(value) => value,
+                    None if is_name(&operand.text) => {
+                        self.fixups.push(Fixup { address, kind: FixupKind::Long, token: operand.clone() });
+                        0
+                    }
+                    None => return Err(error_at(&operand, format!("expected an address, found {}", operand.text))),
+                };
+                if !(0..=0xffff).contains(&value) {
+                    return Err(error_at(&operand, format!("{} is out of range for a 16 bit address", operand.text)));
+                }
+                self.emit_byte((value >> 8) as u8, &operand)?;
+                self.emit_byte(value as u8, &operand)
+            }
+            ":=" => self.address_instruction(Instruction::LoadIndex(0), token),
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Instruction::AddIndex(x), token)
+            }
+            _ => Err(error_at(&operator, format!("expected := or += after i, found {}", operator.text))),
+        }
+    }
+
+    /// Every statement starting with a register, e.g. `v0 += 1`
+    fn register_statement(&mut self, x: u8, token: &Token) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+        let instruction = match operator.text.as_str() {
+            ":=" if self.peek_is("random") => {
+                self.next()?;
+                Instruction::Random(x, self.byte()?)
+            }
+            ":=" if self.peek_is("delay") => {
+                self.next()?;
+                Instruction::GetDelay(x)
+            }
+            ":=" if self.peek_is("key") => {
+                self.next()?;
+                Instruction::WaitKey(x)
+            }
+            ":=" => match self.register_or_byte()? {
+                Ok(y) => Instruction::Move(x, y),
+                Err(kk) => Instruction::LoadByte(x, kk),
+            },
+            "+=" => match self.register_or_byte()? {
+                Ok(y) => Instruction::Add(x, y),
+                Err(kk) => Instruction::AddByte(x, kk),
+            },
+            "-=" => match self.register_or_byte()? {
+                Ok(y) => Instruction::Sub(x, y),
+                Err(kk) => Instruction::AddByte(x, kk.wrapping_neg()),
+            },
+            "|=" => Instruction::Or(x, self.register()?),
+            "&=" => Instruction::And(x, self.register()?),
+            "^=" => Instruction::Xor(x, self.register()?),
+            "=-" => Instruction::SubReverse(x, self.register()?),
+            ">>=" => Instruction::ShiftRight(x, self.register()?),
+            "<<=" => Instruction::ShiftLeft(x, self.register()?),
+            _ => return Err(error_at(&operator, format!("unknown operator {}", operator.text))),
+        };
+        self.emit(instruction, token)
+    }
+
+    /// Parse the condition of an `if` or `while`
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        let mut setup = Vec::new();
+        let skip_unless = match operator.text.as_str() {
+            "key" => Instruction::SkipKeyNotPressed(x),
+            "-key" => Instruction::SkipKeyPressed(x),
+            "==" => match self.register_or_byte()? {
+                Ok(y) => Instruction::SkipNotEqual(x, y),
+                Err(kk) => Instruction::SkipNotEqualByte(x, kk),
+            },
+            "!=" => match self.register_or_byte()? {
+                Ok(y) => Instruction::SkipEqual(x, y),
+                Err(kk) => Instruction::SkipEqualByte(x, kk),
+            },
+            "<" | ">=" => {
+                // vf = x - y leaves the flag set when x >= y
+                match self.register_or_byte()? {
+                    Ok(y) => setup.extend_from_slice(&[Instruction::Move(COMPARE_TEMP, x), Instruction::Sub(COMPARE_TEMP, y)]),
+                    Err(kk) => setup.extend_from_slice(&[Instruction::LoadByte(COMPARE_TEMP, kk), Instruction::SubReverse(COMPARE_TEMP, x)]),
+                }
+                if operator.text == "<" { Instruction::SkipNotEqualByte(COMPARE_TEMP, 0) } else { Instruction::SkipEqualByte(COMPARE_TEMP, 0) }
+            }
+            ">" | "<=" => {
+                // vf = y - x leaves the flag set when y >= x
+                match self.register_or_byte()? {
+                    Ok(y) => setup.push(Instruction::Move(COMPARE_TEMP, y)),
+                    Err(kk) => setup.push(Instruction::LoadByte(COMPARE_TEMP, kk)),
+                }
+                setup.push(Instruction::Sub(COMPARE_TEMP, x));
+                if operator.text == ">" { Instruction::SkipNotEqualByte(COMPARE_TEMP, 0) } else { Instruction::SkipEqualByte(COMPARE_TEMP, 0) }
+            }
+            _ => return Err(error_at(&operator, format!("unknown comparison {}", operator.text))),
+        };
+        Ok(Condition { setup, skip_unless })
+    }
+
+    /// Emit a condition for `if ... then`, or with `jump` set the jump taken
+    /// when it is false for `begin` and `while`, returning the jump's address
+    fn emit_condition(&mut self, condition: &Condition, jump: bool, token: &Token) -> Result<u16, AssembleError> {
+        for instruction in &condition.setup {
+            self.emit(*instruction, token)?;
+        }
+        if !jump {
+            self.emit(condition.skip_unless, token)?;
+            return Ok(0);
+        }
+        self.emit(inverse_skip(condition.skip_unless), token)?;
+        let address = self.here as u16;
+        self.emit(Instruction::Jump(0), token)?;
+        Ok(address)
+    }
+
+    /// Point the jump at `address` to the current address
+    fn patch_jump(&mut self, address: u16, token: &Token) -> Result<(), AssembleError> {
+        check_jump_target(self.here, token)?;
+        let offset = (address - PROGRAM_START) as usize;
+        self.rom[offset] = self.rom[offset] & 0xf0 | (self.here >> 8) as u8;
+        self.rom[offset + 1] = self.here as u8;
+        Ok(())
+    }
+
+    /// Splice a macro's body into the token stream, replacing its parameters with the arguments
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(error_at(token, "too many macro expansions, is a macro using itself?".to_string()));
+        }
+        let parameters = self.macros[&token.text].parameters.clone();
+        let mut arguments = HashMap::new();
+        for parameter in parameters {
+            arguments.insert(parameter, self.next()?);
+        }
+        let body: Vec<Token> = self.macros[&token.text].body.iter()
+            .map(|body_token| match arguments.get(&body_token.text) {
+                Some(argument) if !body_token.quoted => Token { text: argument.text.clone(), quoted: argument.quoted, ..body_token.clone() },
+                _ => body_token.clone(),
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    /// The tokens between a `{` and its matching `}`
+    fn braces(&mut self) -> Result<Vec<Token>, AssembleError> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" if !token.quoted => depth += 1,
+                "}" if !token.quoted && depth == 0 => return Ok(tokens),
+                "}" if !token.quoted => depth -= 1,
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Evaluate a `:calc` expression. As in Octo, operators have no precedence and
+    /// are applied right to left, so `2 * 3 + 1` is 8. Use parentheses to group
+    fn calc(&self, tokens: &[Token], location: &Token) -> Result<f64, AssembleError> {
+        let mut position = 0;
+        let value = self.calc_expression(tokens, &mut position, location)?;
+        match tokens.get(position) {
+            Some(token) => Err(error_at(token, format!("unexpected {} in expression", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    fn calc_expression(&self, tokens: &[Token], position: &mut usize, location: &Token) -> Result<f64, AssembleError> {
+        let left = self.calc_term(tokens, position, location)?;
+        let operator = match tokens.get(*position) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.calc_expression(tokens, position, location)?;
+        let (a, b) = (left as i64, right as i64);
+        let value = match operator.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.wrapping_shl(b as u32) as f64,
+            ">>" => a.wrapping_shr(b as u32) as f64,
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(error_at(operator, format!("unknown operator {} in expression", operator.text))),
+        };
+        Ok(value)
+    }
+
+    fn calc_term(&self, tokens: &[Token], position: &mut usize, location: &Token) -> Result<f64, AssembleError> {
+        let token = tokens.get(*position).ok_or_else(|| error_at(location, "incomplete expression".to_string()))?;
+        *position += 1;
+        let value = match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens, position, location)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => *position += 1,
+                    _ => return Err(error_at(token, "( without )".to_string())),
+                }
+                value
+            }
+            "-" => -self.calc_term(tokens, position, location)?,
+            "~" => !(self.calc_term(tokens, position, location)? as i64) as f64,
+            "!" => (self.calc_term(tokens, position, location)? == 0.0) as i64 as f64,
+            "floor" => self.calc_term(tokens, position, location)?.floor(),
+            "abs" => self.calc_term(tokens, position, location)?.abs(),
+            // The byte already assembled at an address
+            "@" => {
+                let address = self.calc_term(tokens, position, location)? as i64 - PROGRAM_START as i64;
+                usize::try_from(address).ok().and_then(|offset| self.rom.get(offset)).copied().unwrap_or(0) as f64
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => match self.lookup_value(token) {
+                Some(value) => value as f64,
+                None => return Err(error_at(token, format!("unknown value {} in expression", token.text))),
+            },
+        };
+        Ok(value)
+    }
+
+    /// Splice the tokens of an included file in after the `:include`
+    fn include(&mut self) -> Result<(), AssembleError> {
+        let name = self.next()?;
+        self.includes += 1;
+        if self.includes > MAX_INCLUDES {
+            return Err(error_at(&name, "too many includes, is a file including itself?".to_string()));
+        }
+        let path = name.file.parent().unwrap_or_else(|| Path::new("")).join(&name.text);
+        let source = fs::read_to_string(&path)
+            .map_err(|e| error_at(&name, format!("unable to include {}: {}", path.display(), e)))?;
+        let tokens = tokenize(&source, &Rc::new(path));
+        self.tokens.splice(self.position..self.position, tokens);
+        Ok(())
+    }
+
+    /// Emit an instruction taking a 12 bit address, which may be a label defined later
+    fn address_instruction(&mut self, instruction: Instruction, token: &Token) -> Result<(), AssembleError> {
+        let operand = self.next()?;
+        let address = match self.lookup_value(&operand) {
+            Some(value) if (0..=0xfff).contains(&value) => value as u16,
+            Some(_) => return Err(error_at(&operand, format!("{} is out of range for a 12 bit address", operand.text))),
+            None if is_name(&operand.text) => {
+                self.fixups.push(Fixup { address: self.here as u16, kind: FixupKind::Nnn, token: operand.clone() });
+                0
+            }
+            None => return Err(error_at(&operand, format!("expected an address, found {}", operand.text))),
+        };
+        let instruction = match instruction {
+            Instruction::Call(_) => Instruction::Call(address),
+            Instruction::Jump(_) => Instruction::Jump(address),
+            Instruction::JumpOffset(_) => Instruction::JumpOffset(address),
+            _ => Instruction::LoadIndex(address),
+        };
+        self.emit(instruction, token)
+    }
+
+    /// The current address, for marking it with a label or breakpoint
+    fn here_address(&self, token: &Token) -> Result<u16, AssembleError> {
+        u16::try_from(self.here).map_err(|_| error_at(token, format!("{} is past the end of 64 KiB of memory", token.text)))
+    }
+
+    fn emit(&mut self, instruction: Instruction, token: &Token) -> Result<(), AssembleError> {
+        if let Some(address) = instruction.address() {
+            check_jump_target(address as u32, token)?;
+        }
+        let opcode = instruction.encode();
+        self.emit_byte((opcode >> 8) as u8, token)?;
+        self.emit_byte(opcode as u8, token)
+    }
+
+    fn emit_byte(&mut self, value: u8, token: &Token) -> Result<(), AssembleError> {
+        if self.here > 0xffff {
+            return Err(error_at(token, "program is larger than 64 KiB".to_string()));
+        }
+        let offset = (self.here - PROGRAM_START as u32) as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = value;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let (file, line) = match self.tokens.last() {
+                    Some(token) => (token.file.display().to_string(), token.line),
+                    None => ("<input>".to_string(), 0),
+                };
+                Err(AssembleError { file, line, message: "unexpected end of input".to_string() })
+            }
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.get(self.position).map_or(false, |token| !token.quoted && token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(error_at(&token, format!("expected {}, found {}", text, token.text)));
+        }
+        Ok(())
+    }
+
+    /// A new label, constant or alias name
+    fn name(&mut self) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if !is_name(&token.text) || parse_register(&token.text).is_some() {
+            return Err(error_at(&token, format!("{} can't be used as a name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn lookup_register(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    /// A number, constant or already defined label
+    fn lookup_value(&self, token: &Token) -> Option<i64> {
+        parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).copied())
+            .or_else(|| self.labels.get(&token.text).map(|address| *address as i64))
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.lookup_register(&token.text)
+            .ok_or_else(|| error_at(&token, format!("expected a register, found {}", token.text)))
+    }
+
+    fn value(&mut self) -> Result<i64, AssembleError> {
+        let token = self.next()?;
+        self.lookup_value(&token)
+            .ok_or_else(|| error_at(&token, format!("expected a number, found {}", token.text)))
+    }
+
+    fn byte(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        match self.lookup_value(&token) {
+            Some(value) => byte_value(value, &token),
+            None => Err(error_at(&token, format!("expected a byte, found {}", token.text))),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        match self.lookup_value(&token) {
+            Some(value) if (0..=0xf).contains(&value) => Ok(value as u8),
+            _ => Err(error_at(&token, format!("expected a number from 0 to 15, found {}", token.text))),
+        }
+    }
+
+    /// A register as Ok or a byte as Err, for instructions taking either
+    fn register_or_byte(&mut self) -> Result<Result<u8, u8>, AssembleError> {
+        let token = self.next()?;
+        if let Some(register) = self.lookup_register(&token.text) {
+            return Ok(Ok(register));
+        }
+        match self.lookup_value(&token) {
+            Some(value) => Ok(Err(byte_value(value, &token)?)),
+            None => Err(error_at(&token, format!("expected a register or byte, found {}", token.text))),
+        }
+    }
+}
+
+/// The skip with the opposite condition
+fn inverse_skip(skip: Instruction) -> Instruction {
+    match skip {
+        Instruction::SkipEqualByte(x, kk) => Instruction::SkipNotEqualByte(x, kk),
+        Instruction::SkipNotEqualByte(x, kk) => Instruction::SkipEqualByte(x, kk),
+        Instruction::SkipEqual(x, y) => Instruction::SkipNotEqual(x, y),
+        Instruction::SkipNotEqual(x, y) => Instruction::SkipEqual(x, y),
+        Instruction::SkipKeyPressed(x) => Instruction::SkipKeyNotPressed(x),
+        Instruction::SkipKeyNotPressed(x) => Instruction::SkipKeyPressed(x),
+        _ => unreachable!("{} is not a skip", skip),
+    }
+}
+
+/// Bytes may be written signed, e.g. -1 for 0xff
+fn byte_value(value: i64, token: &Token) -> Result<u8, AssembleError> {
+    if (-128..=255).contains(&value) {
+        Ok(value as u8)
+    }
+    else {
+        Err(error_at(token, format!("{} doesn't fit in a byte", token.text)))
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    text.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+fn error_at(token: &Token, message: String) -> AssembleError {
+    AssembleError { file: token.file.display().to_string(), line: token.line, message }
+}
+
+/// Instructions only hold a 12 bit address, so control flow can't reach past 0xfff
+fn check_jump_target(address: u32, token: &Token) -> Result<(), AssembleError> {
+    if address > 0xfff {
+        return Err(error_at(token, "control flow past 0xfff can't be jumped to".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::{decode, Syntax};
+
+    #[test]
+    pub fn test_instructions() {
+        let program = assemble("clear v0 := 0x2A i := 0x300 sprite v0 v1 5 v2 += -1 if v3 != 7 then return").unwrap();
+        assert_eq!(program.rom, vec![0x00, 0xE0, 0x60, 0x2A, 0xA3, 0x00, 0xD0, 0x15, 0x72, 0xFF, 0x33, 0x07, 0x00, 0xEE]);
+    }
+
+    #[test]
+    pub fn test_octo_syntax_round_trip() {
+        // Every instruction printed by the disassembler assembles back to its opcode
+        for opcode in (0..=0xffffu32).step_by(7) {
+            let instruction = match decode(opcode as u16) {
+                Some(Instruction::LoadLongIndex) | None => continue,
+                Some(instruction) => instruction,
+            };
+            let source = instruction.format(Syntax::Octo);
+            let program = assemble(&source).unwrap_or_else(|e| panic!("{}: {}", source, e));
+            assert_eq!(program.rom, vec![(opcode >> 8) as u8, opcode as u8], "{}", source);
+        }
+    }
+
+    #[test]
+    pub fn test_labels_constants_and_aliases() {
+        let source = "
+            :const COUNT 3
+            :alias counter v4
+            : main
+                counter := COUNT
+                draw        # called before it is defined
+                jump main
+            : draw
+                i := sprite
+                ;
+            : sprite
+                0b11110000 0x90 :byte 255
+        ";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(program.labels["main"], 0x200);
+        assert_eq!(program.labels["draw"], 0x206);
+        assert_eq!(program.labels["sprite"], 0x20A);
+        assert_eq!(program.rom, vec![
+            0x64, 0x03, 0x22, 0x06, 0x12, 0x00,
+            0xA2, 0x0A, 0x00, 0xEE,
+            0xF0, 0x90, 0xFF,
+        ]);
+    }
+
+    #[test]
+    pub fn test_long_index_and_org() {
+        let program = assemble("i := long data :org 0x210 : data 1").unwrap();
+        assert_eq!(program.rom.len(), 0x11);
+        assert_eq!(&program.rom[..4], &[0xF0, 0x00, 0x02, 0x10]);
+        assert_eq!(program.rom[0x10], 1);
+    }
+
+    #[test]
+    pub fn test_save_load_ranges() {
+        let program = assemble("save v2 - v5 load v1 save v3").unwrap();
+        assert_eq!(program.rom, vec![0x52, 0x52, 0xF1, 0x65, 0xF3, 0x55]);
+    }
+
+    #[test]
+    pub fn test_include() {
+        let directory = std::env::temp_dir().join(format!("chip8-asm-include-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("main.8o"), ":include \"lib.8o\"\nv1 := ONE\n").unwrap();
+        fs::write(directory.join("lib.8o"), ":const ONE 1\n").unwrap();
+
+        let program = assemble_file(&directory.join("main.8o")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(program.rom, vec![0x61, 0x01]);
+    }
+
+    /// Run a program until it loops on the spot
+    fn run(source: &str) -> crate::chip8::Chip8 {
+        let program = assemble(source).unwrap_or_else(|e| panic!("{}", e));
+        let mut chip8 = crate::chip8::Chip8::builder().rom(&program.rom).build();
+        for _ in 0..1000 {
+            chip8.run_instruction().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    pub fn test_comparisons() {
+        for operator in &["==", "!=", "<", ">", "<=", ">="] {
+            for &(a, b) in &[(1u8, 2u8), (2, 2), (3, 2), (0, 255)] {
+                let source = format!(
+                    "v0 := {a} v1 := {b}
+                     if v0 {op} v1 then v2 := 1
+                     if v0 {op} {b} begin v3 := 1 else v3 := 2 end
+                     : done jump done",
+                    a = a, b = b, op = operator,
+                );
+                let chip8 = run(&source);
+                let wanted = match *operator {
+                    "==" => a == b,
+                    "!=" => a != b,
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    _ => a >= b,
+                };
+                assert_eq!(chip8.cpu.read_reg(2), wanted as u8, "{} {} {}", a, operator, b);
+                assert_eq!(chip8.cpu.read_reg(3), if wanted { 1 } else { 2 }, "{} {} {}", a, operator, b);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_loops() {
+        // Count v0 up to 10, adding to v1 only on even numbers
+        let chip8 = run("
+            loop
+                while v0 != 10
+                v0 += 1
+                v2 := 1
+                v2 &= v0
+                if v2 == 0 begin
+                    v1 += 1
+                end
+            again
+            : done jump done
+        ");
+        assert_eq!(chip8.cpu.read_reg(0), 10);
+        assert_eq!(chip8.cpu.read_reg(1), 5);
+    }
+
+    #[test]
+    pub fn test_macros_and_calc() {
+        let source = "
+            :macro add-twice register amount {
+                register += amount
+                register += amount
+            }
+            :const BASE 0x10
+            :calc DOUBLE { BASE * 2 + 1 }
+            :calc GROUPED { ( BASE * 2 ) + 1 }
+            add-twice v3 DOUBLE
+            :breakpoint after-macro
+            v4 := GROUPED
+            :byte { HERE - 0x200 }
+        ";
+        let program = assemble(source).unwrap();
+
+        // Right to left, so DOUBLE is 0x10 * 3
+        assert_eq!(program.rom, vec![0x73, 0x30, 0x73, 0x30, 0x64, 0x21, 0x06]);
+        assert_eq!(program.breakpoints.get(&0x204).map(String::as_str), Some("after-macro"));
+    }
+
+    #[test]
+    pub fn test_sprite_literals() {
+        let program = assemble(": ship 0b00111100 0b01111110 0b11111111").unwrap();
+        assert_eq!(program.rom, vec![0x3C, 0x7E, 0xFF]);
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let error = assemble("clear\nv0 := 300").unwrap_err();
+        assert_eq!(error, AssembleError { file: "<input>".to_string(), line: 2, message: "300 doesn't fit in a byte".to_string() });
+
+        assert_eq!(assemble("jump nowhere").unwrap_err().message, "undefined name nowhere");
+        assert_eq!(assemble("i := long BIG :const BIG 0x10000").unwrap_err().message, "constant BIG is used before it is defined");
+        assert_eq!(assemble("jump LATER :calc LATER { 0x200 }").unwrap_err().message, "constant LATER is used before it is defined");
+        assert_eq!(assemble(": a : a").unwrap_err().message, "label a is already defined");
+        assert_eq!(assemble("v0 :=").unwrap_err().message, "unexpected end of input");
+        assert_eq!(assemble("v0 *= v1").unwrap_err().message, "unknown operator *=");
+        assert_eq!(assemble("sprite v0 v1 16").unwrap_err().message, "expected a number from 0 to 15, found 16");
+        assert!(assemble(":include \"missing.8o\"").unwrap_err().message.starts_with("unable to include"));
+        assert_eq!(assemble("loop v0 += 1").unwrap_err().message, "loop without again");
+        assert_eq!(assemble("if v0 == 1 begin").unwrap_err().message, "begin without end");
+        assert_eq!(assemble("end").unwrap_err().message, "end without if ... begin");
+        assert_eq!(assemble("while v0 == 1").unwrap_err().message, "while outside of loop");
+        assert_eq!(assemble(":macro forever { forever } forever").unwrap_err().message, "too many macro expansions, is a macro using itself?");
+        assert_eq!(assemble(":org 0xfffe i := long 0").unwrap_err().message, "program is larger than 64 KiB");
+        assert_eq!(assemble(":org 0xfffe 0 0 : end").unwrap_err().message, "end is past the end of 64 KiB of memory");
+        assert_eq!(assemble(":org 0xffff 0 :breakpoint end").unwrap_err().message, "end is past the end of 64 KiB of memory");
+        assert_eq!(assemble(":org 0x1000 loop v0 += 1 again").unwrap_err().message, "control flow past 0xfff can't be jumped to");
+    }
+}
@@ -0,0 +1,190 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Frequency of the beeper tone in Hz
+pub const BEEP_FREQUENCY: f32 = 440.0;
+
+/// Volume of the beeper tone, between 0.0 and 1.0
+pub const BEEP_AMPLITUDE: f32 = 0.25;
+
+/// Default XO-CHIP pitch, plays the pattern at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// XO-CHIP 1-bit audio: 128 bits played in a loop at a rate set by the pitch register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub pattern: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+
+    /// Number of pattern bits played per second
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+}
+
+impl Default for AudioPattern {
+
+    /// A square wave, which is what Octo plays before a pattern is loaded
+    fn default() -> Self {
+        AudioPattern {
+            pattern: [0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff],
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+/// Destination for the CHIP-8 beeper, e.g. a sound device
+pub trait AudioSink {
+    /// Start or stop the beeper. Only called when the state changes
+    fn set_beeping(&mut self, beeping: bool);
+
+    /// Play an XO-CHIP audio pattern instead of the plain tone. Only called when the pattern changes
+    fn set_pattern(&mut self, _pattern: AudioPattern) {}
+}
+
+/// Sink which discards the beeper, used when running without sound
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn set_beeping(&mut self, _beeping: bool) {}
+}
+
+/// Sink which records every change of the beeper state. Clones share the
+/// same recording, so a handle can be kept after handing one to the emulator
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    events: Rc<RefCell<Vec<bool>>>,
+    patterns: Rc<RefCell<Vec<AudioPattern>>>,
+}
+
+impl RecordingSink {
+
+    /// Creates a new, empty recording
+    pub fn new() -> Self {
+        RecordingSink::default()
+    }
+
+    /// Get every state the beeper was set to, in order
+    pub fn events(&self) -> Vec<bool> {
+        self.events.borrow().clone()
+    }
+
+    /// Get every XO-CHIP pattern that was set, in order
+    pub fn patterns(&self) -> Vec<AudioPattern> {
+        self.patterns.borrow().clone()
+    }
+}
+
+impl AudioSink for RecordingSink {
+    fn set_beeping(&mut self, beeping: bool) {
+        self.events.borrow_mut().push(beeping);
+    }
+
+    fn set_pattern(&mut self, pattern: AudioPattern) {
+        self.patterns.borrow_mut().push(pattern);
+    }
+}
+
+/// Square wave generator producing the beeper tone one sample at a time
+pub struct SquareWave {
+    frequency: f32,
+    sample_rate: f32,
+    phase: f32,
+}
+
+impl SquareWave {
+
+    /// Creates a square wave of the given frequency for a device running at `sample_rate`
+    pub fn new(frequency: f32, sample_rate: u32) -> Self {
+        SquareWave {
+            frequency,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+        }
+    }
+
+    /// Get the next sample of the wave
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = if self.phase < 0.5 { BEEP_AMPLITUDE } else { -BEEP_AMPLITUDE };
+        self.phase = (self.phase + self.frequency / self.sample_rate) % 1.0;
+        sample
+    }
+}
+
+/// Plays back an XO-CHIP audio pattern one sample at a time
+pub struct PatternWave {
+    pattern: AudioPattern,
+    step: f32,
+    position: f32,
+}
+
+impl PatternWave {
+
+    /// Creates a wave playing `pattern` on a device running at `sample_rate`
+    pub fn new(pattern: AudioPattern, sample_rate: u32) -> Self {
+        PatternWave {
+            pattern,
+            step: pattern.playback_rate() / sample_rate as f32,
+            position: 0.0,
+        }
+    }
+
+    /// Get the next sample of the wave
+    pub fn next_sample(&mut self) -> f32 {
+        let bit = self.position as usize;
+        let set = self.pattern.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+        self.position = (self.position + self.step) % 128.0;
+        if set { BEEP_AMPLITUDE } else { -BEEP_AMPLITUDE }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_square_wave_period() {
+        // 8 samples per period
+        let mut wave = SquareWave::new(1000.0, 8000);
+        let samples: Vec<f32> = (0..16).map(|_| wave.next_sample()).collect();
+
+        for (index, sample) in samples.iter().enumerate() {
+            let expected = if index % 8 < 4 { BEEP_AMPLITUDE } else { -BEEP_AMPLITUDE };
+            assert_eq!(*sample, expected);
+        }
+    }
+
+    #[test]
+    pub fn test_recording_sink_shares_events() {
+        let recording = RecordingSink::new();
+        let mut sink: Box<dyn AudioSink> = Box::new(recording.clone());
+
+        sink.set_beeping(true);
+        sink.set_beeping(false);
+
+        assert_eq!(recording.events(), vec![true, false]);
+    }
+
+    #[test]
+    pub fn test_pattern_playback_rate() {
+        let mut pattern = AudioPattern::default();
+        assert_eq!(pattern.playback_rate(), 4000.0);
+
+        pattern.pitch = DEFAULT_PITCH + 48;
+        assert_eq!(pattern.playback_rate(), 8000.0);
+    }
+
+    #[test]
+    pub fn test_pattern_wave() {
+        let mut pattern = [0; 16];
+        pattern[0] = 0xa0;
+        // One bit per sample
+        let mut wave = PatternWave::new(AudioPattern { pattern, pitch: DEFAULT_PITCH }, 4000);
+        let samples: Vec<f32> = (0..4).map(|_| wave.next_sample()).collect();
+
+        assert_eq!(samples, vec![BEEP_AMPLITUDE, -BEEP_AMPLITUDE, BEEP_AMPLITUDE, -BEEP_AMPLITUDE]);
+    }
+}
@@ -0,0 +1,36 @@
+//! Run a ROM, or Octo source ending in `.8o`, without a window for a fixed number of frames or instructions, then
+//! dump the screen. Exits with 0 on success, 1 if the ROM crashed or the screen
+//! didn't match `--expect`, and 2 on bad arguments.
+//!
+//! ```text
+//! chip8-headless ROM [--quirks vip|chip48|schip|xochip] [--frames N | --cycles N]
+//!                    [--ipf N] [--seed N] [--keys SCRIPT] [--keys-file PATH]
+//!                    [--text PATH] [--png PATH] [--expect PATH] [--trace PATH]
+//!                    [--vip-timing]
+//! ```
+//!
+//! It takes the same options as `chip8 run --headless`. `--vip-timing` runs
+//! instructions at COSMAC VIP speed, ignoring `--ipf`
+
+extern crate chip8;
+
+use chip8::cli::{self, HEADLESS_USAGE};
+use std::env;
+use std::process;
+
+fn main() {
+    let args = cli::parse_headless(env::args().skip(1).collect()).unwrap_or_else(|e| {
+        eprintln!("chip8-headless: {}", e);
+        eprint!("{}", HEADLESS_USAGE);
+        process::exit(2);
+    });
+
+    let program = cli::load_program(&args).unwrap_or_else(|e| {
+        eprintln!("chip8-headless: {}", e);
+        process::exit(2);
+    });
+    if let Err(e) = cli::run_headless(&program, &args) {
+        eprintln!("chip8-headless: {}", e);
+        process::exit(1);
+    }
+}
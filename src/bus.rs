@@ -1,51 +1,165 @@
+use crate::audio::AudioPattern;
 use crate::display::Display;
 use crate::keyboard::Keyboard;
-use crate::memory;
 use crate::memory::Memory;
-use std::time::{Instant};
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::watchpoint::{WatchHit, Watchpoint};
+use std::cell::Cell;
 
 pub struct Bus {
     display: Display,
     keyboard: Keyboard,
     memory: Memory,
     delay_timer: u8,
-    delay_timer_set_time: Instant
+    sound_timer: u8,
+    audio_pattern: Option<AudioPattern>,
+    watchpoints: Vec<Watchpoint>,
+    /// Set by reads too, hence the Cell
+    watch_hit: Cell<Option<WatchHit>>,
+    /// A frame boundary has passed since the last sprite was drawn
+    vblank: bool,
 }
 
 impl Bus {
 
     pub fn new() -> Self {
+        Bus::with_memory(Memory::new())
+    }
+
+    /// Create a Bus with `size` bytes of memory, used for the XO-CHIP address space
+    pub fn with_memory_size(size: usize) -> Self {
+        Bus::with_memory(Memory::with_size(size))
+    }
+
+    fn with_memory(memory: Memory) -> Self {
         Bus {
-            memory: Memory::new(),
+            memory,
             keyboard: Keyboard::new(),
             display: Display::new(),
             delay_timer: 0,
-            delay_timer_set_time: Instant::now()
+            sound_timer: 0,
+            audio_pattern: None,
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            vblank: true,
         }
     }
 
     pub fn memory_read_byte(&self, address: u16) -> u8 {
-        self.memory.read_byte(address)
+        let value = self.memory.read_byte(address);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, value, None);
+        }
+        value
     }
 
     pub fn memory_write_byte(&mut self, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            let old = self.memory.read_byte(address);
+            self.check_watchpoints(address, old, Some(value));
+        }
         self.memory.write_byte(address, value)
     }
 
+    /// Read memory without triggering watchpoints, for fetching instructions and debuggers
+    pub fn memory_peek_byte(&self, address: u16) -> u8 {
+        self.memory.read_byte(address)
+    }
+
+    /// Remember the first access since the last `take_watch_hit` that triggers a watchpoint
+    fn check_watchpoints(&self, address: u16, old: u8, new: Option<u8>) {
+        if self.watch_hit.get().is_some() {
+            return;
+        }
+        // Watch the address the access lands on after wrapping around the end of memory
+        let address = self.memory.wrap_address(address);
+        let access = new.map(|new| (old, new));
+        if let Some(watchpoint) = self.watchpoints.iter().find(|watchpoint| watchpoint.matches(address, access)) {
+            self.watch_hit.set(Some(WatchHit { kind: watchpoint.kind, address, old, new: new.unwrap_or(old) }));
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Remove every watchpoint covering the address, returning whether there were any
+    pub fn remove_watchpoints(&mut self, address: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| !watchpoint.contains(address));
+        self.watchpoints.len() != count
+    }
+
+    /// Remove watchpoints equal to `watchpoint`, returning whether there were any
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|existing| *existing != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Take the access which triggered a watchpoint, if any did since the last call
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
     pub fn memory_get_size(&self) -> usize {
-        memory::SIZE
+        self.memory.get_size()
+    }
+
+    pub fn draw_byte(&mut self, byte: u8, x: u8, y: u8, clip: bool, plane: u8) -> bool {
+        self.display.draw_byte(byte, x, y, clip, plane)
     }
 
-    pub fn draw_byte(&mut self, byte: u8, x: u8, y: u8) -> bool {
-        self.display.draw_byte(byte, x, y)
+    pub fn set_planes(&mut self, planes: u8) {
+        self.display.set_planes(planes)
+    }
+
+    pub fn get_planes(&self) -> u8 {
+        self.display.get_planes()
     }
 
     pub fn clear_screen(&mut self) {
         self.display.clear()
     }
 
-    pub fn set_key_pressed(&mut self, key: Option<u8>) {
-        self.keyboard.set_key_pressed(key)
+    pub fn set_hires(&mut self, hires: bool) {
+        self.display.set_hires(hires)
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.display.scroll_down(rows)
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.display.scroll_up(rows)
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.display.scroll_right(columns)
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.display.scroll_left(columns)
+    }
+
+    pub fn get_display_width(&self) -> usize {
+        self.display.get_width()
+    }
+
+    pub fn get_display_height(&self) -> usize {
+        self.display.get_height()
+    }
+
+    pub fn key_down(&mut self, key_code: u8) {
+        self.keyboard.key_down(key_code)
+    }
+
+    pub fn key_up(&mut self, key_code: u8) {
+        self.keyboard.key_up(key_code)
     }
 
     pub fn is_key_pressed(&self, key_code: u8) -> bool {
@@ -57,30 +171,108 @@ impl Bus {
     }
 
     pub fn set_delay_timer(&mut self, value: u8) {
-        self.delay_timer_set_time = Instant::now();
         self.delay_timer = value;
     }
 
     pub fn get_delay_timer(&self) -> u8 {
-        let diff = Instant::now() - self.delay_timer_set_time;
-        let ms = diff.as_millis() as u64;
-        let ticks: u64 = ms / 16;
-        if ticks >= self.delay_timer as u64 {
-            0
-        }
-        else {
-            self.delay_timer - ticks as u8
-        }
+        self.delay_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Load the 16 byte XO-CHIP audio pattern
+    pub fn set_audio_pattern(&mut self, pattern: [u8; 16]) {
+        let pitch = self.get_audio_pattern().pitch;
+        self.audio_pattern = Some(AudioPattern { pattern, pitch });
+    }
+
+    /// Set the XO-CHIP playback rate of the audio pattern
+    pub fn set_pitch(&mut self, pitch: u8) {
+        let pattern = self.get_audio_pattern().pattern;
+        self.audio_pattern = Some(AudioPattern { pattern, pitch });
+    }
+
+    /// Get the XO-CHIP audio pattern, the default pattern is used until a program sets one
+    pub fn get_audio_pattern(&self) -> AudioPattern {
+        self.audio_pattern.unwrap_or_default()
+    }
+
+    /// Whether a program has used the XO-CHIP audio instructions
+    pub fn has_audio_pattern(&self) -> bool {
+        self.audio_pattern.is_some()
+    }
+
+    /// Mark the start of a new frame, letting a DXYN waiting for it draw
+    pub fn signal_vblank(&mut self) {
+        self.vblank = true;
+    }
+
+    /// Whether a DXYN may draw now, without clearing it like `take_vblank`
+    pub(crate) fn has_vblank(&self) -> bool {
+        self.vblank
+    }
+
+    /// Whether a frame boundary has passed since this was last called
+    pub(crate) fn take_vblank(&mut self) -> bool {
+        std::mem::replace(&mut self.vblank, false)
+    }
+
+    /// Count both timers down by one, must be called once per emulated 60 Hz frame
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
     }
 
     pub fn get_display_buffer(&self) -> &[u8] {
         self.display.get_display_buffer()
     }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        writer.write_bool(self.vblank);
+        writer.write_bool(self.audio_pattern.is_some());
+        let audio_pattern = self.get_audio_pattern();
+        writer.write_bytes(&audio_pattern.pattern);
+        writer.write_u8(audio_pattern.pitch);
+        self.display.save_state(writer);
+        self.keyboard.save_state(writer);
+        self.memory.save_state(writer);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.delay_timer = reader.read_u8()?;
+        self.sound_timer = reader.read_u8()?;
+        self.vblank = reader.read_bool()?;
+        let has_audio_pattern = reader.read_bool()?;
+        let mut pattern = [0; 16];
+        reader.read_into(&mut pattern)?;
+        let pitch = reader.read_u8()?;
+        self.audio_pattern = if has_audio_pattern { Some(AudioPattern { pattern, pitch }) } else { None };
+        self.display.load_state(reader)?;
+        self.keyboard.load_state(reader)?;
+        self.memory.load_state(reader)
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus::new()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::cpu::Cpu;
+    use crate::quirks::Quirks;
+    use crate::watchpoint::WatchKind;
 
     #[test]
     pub fn test_memory_read_byte() {
@@ -101,4 +293,65 @@ mod test {
         bus.memory_write_byte(address, value);
         assert_eq!(bus.memory.read_byte(address), value);
     }
+
+    #[test]
+    pub fn test_tick_timers() {
+        let mut bus = Bus::new();
+        bus.set_delay_timer(2);
+        bus.set_sound_timer(1);
+
+        bus.tick_timers();
+        assert_eq!(bus.get_delay_timer(), 1);
+        assert_eq!(bus.get_sound_timer(), 0);
+
+        bus.tick_timers();
+        bus.tick_timers();
+        assert_eq!(bus.get_delay_timer(), 0);
+        assert_eq!(bus.get_sound_timer(), 0);
+    }
+
+    #[test]
+    pub fn test_watchpoints() {
+        let mut bus = Bus::new();
+        bus.add_watchpoint(Watchpoint::new(0x300, 0x301, WatchKind::Change));
+        bus.memory_write_byte(0x300, 0);
+        bus.memory_peek_byte(0x300);
+        assert_eq!(bus.take_watch_hit(), None);
+
+        // Only the first hit is kept until it is taken
+        bus.memory_write_byte(0x301, 5);
+        bus.memory_write_byte(0x300, 6);
+        assert_eq!(bus.take_watch_hit(), Some(WatchHit { kind: WatchKind::Change, address: 0x301, old: 0, new: 5 }));
+        assert_eq!(bus.take_watch_hit(), None);
+
+        bus.add_watchpoint(Watchpoint::new(0x310, 0x310, WatchKind::Read));
+        bus.memory_read_byte(0x310);
+        assert_eq!(bus.take_watch_hit().map(|hit| hit.kind), Some(WatchKind::Read));
+
+        assert!(bus.remove_watchpoints(0x301));
+        assert!(!bus.remove_watchpoints(0x301));
+        assert_eq!(bus.get_watchpoints().len(), 1);
+        assert!(!bus.remove_watchpoint(Watchpoint::new(0x310, 0x310, WatchKind::Write)));
+        assert!(bus.remove_watchpoint(Watchpoint::new(0x310, 0x310, WatchKind::Read)));
+        assert!(bus.get_watchpoints().is_empty());
+    }
+
+    #[test]
+    pub fn test_watchpoint_on_wrapped_address() {
+        // FX55 with I = 0xFFF stores V1 at 0x1000, which wraps to 0x000 on 4 KiB
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        bus.memory_write_byte(0x200, 0xF1);
+        bus.memory_write_byte(0x201, 0x55);
+        cpu.set_i(0xFFF);
+        cpu.write_reg(1, 7);
+        bus.add_watchpoint(Watchpoint::new(0x1000, 0x1000, WatchKind::Write));
+        bus.add_watchpoint(Watchpoint::new(0x000, 0x000, WatchKind::Change));
+        let old = bus.memory_peek_byte(0x000);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(bus.take_watch_hit(), Some(WatchHit { kind: WatchKind::Change, address: 0x000, old, new: 7 }));
+        assert_eq!(bus.memory_peek_byte(0x000), 7);
+    }
 }
\ No newline at end of file
@@ -1,36 +1,241 @@
+use crate::audio::{AudioPattern, AudioSink, NullSink};
 use crate::bus::Bus;
 use crate::cpu;
+use crate::cpu::{CpuError, StepOutcome};
+use crate::instruction::{decode_for, Instruction};
+use crate::memory;
+use crate::quirks::{InstructionSet, Quirks};
+use crate::rng::Xorshift;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::timing;
+use crate::trace::Tracer;
 
 pub struct Chip8 {
     pub bus: Bus,
     pub cpu: cpu::Cpu,
+    audio: Box<dyn AudioSink>,
+    beeping: bool,
+    audio_pattern: Option<AudioPattern>,
+    tracer: Option<Tracer>,
+    vip_timing: bool,
+    /// Machine cycles spent so far in the current frame under VIP timing
+    frame_cycles: u32,
 }
 
 impl Chip8 {
 
-    /// Create a new Chip8 instance
-    pub fn new() -> Self {
+    /// Start building a Chip8 with a ROM, quirks and audio sink
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::default()
+    }
+
+    /// Create a new Chip8 instance running under the given quirks
+    pub fn new(quirks: Quirks) -> Self {
+        Chip8::with_rng(quirks, Xorshift::from_entropy())
+    }
+
+    /// Create a new Chip8 instance whose CXKK values are drawn from `rng`
+    pub fn with_rng(quirks: Quirks, rng: Xorshift) -> Self {
+        let bus = if quirks.instruction_set >= InstructionSet::XoChip {
+            Bus::with_memory_size(memory::XO_CHIP_SIZE)
+        } else {
+            Bus::new()
+        };
+
         Chip8 {
-            bus: Bus::new(),
-            cpu: cpu::Cpu::new(),
+            bus,
+            cpu: cpu::Cpu::with_rng(quirks, rng),
+            audio: Box::new(NullSink),
+            beeping: false,
+            audio_pattern: None,
+            tracer: None,
+            vip_timing: false,
+            frame_cycles: 0,
+        }
+    }
+
+    /// Set where the beeper is played, sound is discarded until this is called
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio = sink;
+        if let Some(pattern) = self.audio_pattern {
+            self.audio.set_pattern(pattern);
+        }
+        if self.beeping {
+            self.audio.set_beeping(true);
         }
     }
 
     /// Load a ROM into memory
     /// Method will panic if the data is larger than the space allocated for the ROM
-    pub fn load_rom(&mut self, data: &Vec<u8>) {
+    pub fn load_rom(&mut self, data: &[u8]) {
 
         assert!(data.len() <= (self.bus.memory_get_size() - (cpu::PROGRAM_START as usize)), "Size of loaded ROM is larger than the allocated RAM of {}", self.bus.memory_get_size());
-        assert!(data.len() > 0, "Loaded ROM is empty");
+        assert!(!data.is_empty(), "Loaded ROM is empty");
 
-        for index in 0..data.len() {
-            self.bus.memory_write_byte(cpu::PROGRAM_START + index as u16, data[index]);
+        for (index, byte) in data.iter().enumerate() {
+            self.bus.memory_write_byte(cpu::PROGRAM_START + index as u16, *byte);
+        }
+    }
+
+    /// Log every instruction run from now on
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stop tracing, handing back the tracer so it can be finished
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Make every instruction take as long as it did on the COSMAC VIP, so a
+    /// frame holds as many instructions as the VIP could run in 1/60 s
+    pub fn set_vip_timing(&mut self, enabled: bool) {
+        self.vip_timing = enabled;
+        self.frame_cycles = 0;
+    }
+
+    pub fn has_vip_timing(&self) -> bool {
+        self.vip_timing
+    }
+
+    /// Whether another instruction fits in the current frame after `instructions_run`
+    /// of them. Under VIP timing the instruction count is ignored in favour of cycles
+    pub fn frame_has_room(&self, instructions_run: usize, instructions_per_frame: usize) -> bool {
+        if self.vip_timing {
+            self.frame_cycles < timing::INTERPRETER_CYCLES_PER_FRAME
+        }
+        else {
+            instructions_run < instructions_per_frame
         }
     }
 
     /// Run CPU instruction
-    pub fn run_instruction(&mut self) {
-        self.cpu.run_instruction(&mut self.bus);
+    pub fn run_instruction(&mut self) -> Result<StepOutcome, CpuError> {
+        // A DXYN waiting for the display runs again later, it's traced when it draws
+        if let Some(tracer) = &mut self.tracer {
+            if !self.cpu.waits_for_vblank(&self.bus) {
+                tracer.trace(&self.cpu, &self.bus);
+            }
+        }
+        if !self.vip_timing {
+            let outcome = self.cpu.run_instruction(&mut self.bus);
+            self.update_audio();
+            return outcome;
+        }
+
+        let pc = self.cpu.get_pc();
+        let opcode = (self.bus.memory_peek_byte(pc) as u16) << 8 | self.bus.memory_peek_byte(pc.wrapping_add(1)) as u16;
+        let vx = self.cpu.read_reg(((opcode >> 8) & 0xf) as u8);
+        let outcome = self.cpu.run_instruction(&mut self.bus);
+        self.update_audio();
+
+        let quirks = self.cpu.get_quirks();
+        match (&outcome, decode_for(opcode, quirks.instruction_set)) {
+            // The rest of the frame is spent waiting
+            (Ok(StepOutcome::WaitingForVblank), _) => {
+                self.frame_cycles = self.frame_cycles.max(timing::INTERPRETER_CYCLES_PER_FRAME);
+            }
+            (Ok(_), Some(instruction)) => {
+                let skipped = instruction.is_skip() && self.cpu.get_pc() != pc.wrapping_add(2);
+                let cycles = timing::instruction_cycles(instruction, vx, skipped);
+                if matches!(instruction, Instruction::Draw(..)) && !quirks.display_wait {
+                    // The VIP always waits for the next vertical blank before drawing
+                    self.frame_cycles = self.frame_cycles.max(timing::INTERPRETER_CYCLES_PER_FRAME) + cycles;
+                }
+                else {
+                    self.frame_cycles += cycles;
+                }
+            }
+            _ => {}
+        }
+        outcome
+    }
+
+    /// Run a single instruction for a debugger. A DXYN waiting for the display
+    /// ends the frame and then draws, so stepping always makes progress
+    pub fn step_instruction(&mut self) -> Result<StepOutcome, CpuError> {
+        match self.run_instruction()? {
+            StepOutcome::WaitingForVblank => {
+                self.tick_timers();
+                self.run_instruction()
+            }
+            outcome => Ok(outcome),
+        }
+    }
+
+    /// End a 60 Hz frame: count the delay and sound timers down and let a
+    /// DXYN waiting for the display draw
+    pub fn tick_timers(&mut self) {
+        self.bus.tick_timers();
+        self.bus.signal_vblank();
+        self.update_audio();
+        // Cycles run past the end of a frame are taken from the next one
+        self.frame_cycles = self.frame_cycles.saturating_sub(timing::INTERPRETER_CYCLES_PER_FRAME);
+    }
+
+    /// Run one 60 Hz frame followed by a timer tick: the given number of
+    /// instructions, or a frame's worth of cycles under VIP timing. The frame
+    /// ends early once a DXYN waits for the display
+    pub fn run_frame(&mut self, instructions_per_frame: usize) -> Result<(), CpuError> {
+        let mut instructions_run = 0;
+        while self.frame_has_room(instructions_run, instructions_per_frame) {
+            instructions_run += 1;
+            match self.run_instruction()? {
+                StepOutcome::Exited | StepOutcome::WaitingForVblank => break,
+                _ => {}
+            }
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// The beeper sounds for as long as the sound timer is non-zero, playing
+    /// the XO-CHIP audio pattern once a program has set one
+    fn update_audio(&mut self) {
+        if self.bus.has_audio_pattern() {
+            let pattern = self.bus.get_audio_pattern();
+            if self.audio_pattern != Some(pattern) {
+                self.audio_pattern = Some(pattern);
+                self.audio.set_pattern(pattern);
+            }
+        }
+
+        let beeping = self.bus.get_sound_timer() > 0;
+        if beeping != self.beeping {
+            self.beeping = beeping;
+            self.audio.set_beeping(beeping);
+        }
+    }
+
+    /// Capture the whole machine so it can be resumed later with `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        self.cpu.save_state(&mut writer);
+        self.bus.save_state(&mut writer);
+        writer.write_u32(self.frame_cycles);
+        writer.into_bytes()
+    }
+
+    /// Resume from a savestate taken with `save_state` under the same quirks.
+    /// The machine is left untouched if the savestate can't be restored
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data)?;
+        let mut cpu = cpu::Cpu::new(self.cpu.get_quirks());
+        let mut bus = Bus::with_memory_size(self.bus.memory_get_size());
+        cpu.load_state(&mut reader)?;
+        bus.load_state(&mut reader)?;
+        let frame_cycles = reader.read_u32()?;
+        reader.finish()?;
+
+        // Watchpoints belong to the debugging session rather than the machine
+        for watchpoint in self.bus.get_watchpoints() {
+            bus.add_watchpoint(*watchpoint);
+        }
+        self.cpu = cpu;
+        self.bus = bus;
+        self.frame_cycles = frame_cycles;
+        self.update_audio();
+        Ok(())
     }
 
     /// Get the display buffer
@@ -38,15 +243,94 @@ impl Chip8 {
         self.bus.get_display_buffer()
     }
 
-    /// Set the key being pressed
-    pub fn set_key_pressed(&mut self, key: Option<u8>) {
-        self.bus.set_key_pressed(key);
+    /// Width of the display buffer, which changes with the SUPER-CHIP resolution
+    pub fn get_display_width(&self) -> usize {
+        self.bus.get_display_width()
+    }
+
+    /// Whether the program ran the SUPER-CHIP exit instruction
+    pub fn has_exited(&self) -> bool {
+        self.cpu.has_exited()
+    }
+
+    /// Whether the program is halted on FX0A waiting for a key press and release
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.cpu.is_waiting_for_key()
+    }
+
+    /// Mark a key of the hex keypad as held down
+    pub fn key_down(&mut self, key_code: u8) {
+        self.bus.key_down(key_code);
+    }
+
+    /// Mark a key of the hex keypad as released
+    pub fn key_up(&mut self, key_code: u8) {
+        self.bus.key_up(key_code);
+    }
+}
+
+/// Builder for a Chip8, defaulting to the COSMAC VIP quirks, no sound, no ROM and a random seed
+#[derive(Default)]
+pub struct Chip8Builder {
+    quirks: Quirks,
+    audio: Option<Box<dyn AudioSink>>,
+    rom: Option<Vec<u8>>,
+    seed: Option<u64>,
+    vip_timing: bool,
+}
+
+impl Chip8Builder {
+
+    /// Set the quirks the ROM was written for
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Set where the beeper is played
+    pub fn audio_sink(mut self, sink: Box<dyn AudioSink>) -> Self {
+        self.audio = Some(sink);
+        self
+    }
+
+    /// Set the ROM loaded at the program start
+    pub fn rom(mut self, data: &[u8]) -> Self {
+        self.rom = Some(data.to_vec());
+        self
+    }
+
+    /// Seed the CXKK random number generator, the same seed and input always give the same run
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Run instructions at the speed of the COSMAC VIP instead of a fixed number per frame
+    pub fn vip_timing(mut self, enabled: bool) -> Self {
+        self.vip_timing = enabled;
+        self
+    }
+
+    /// Create the Chip8. Will panic under the same conditions as `Chip8::load_rom`
+    pub fn build(self) -> Chip8 {
+        let rng = self.seed.map_or_else(Xorshift::from_entropy, Xorshift::new);
+        let mut chip8 = Chip8::with_rng(self.quirks, rng);
+        chip8.set_vip_timing(self.vip_timing);
+        if let Some(sink) = self.audio {
+            chip8.set_audio_sink(sink);
+        }
+        if let Some(rom) = self.rom {
+            chip8.load_rom(&rom);
+        }
+        chip8
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::cpu::DRAW_TWICE_PROGRAM;
+    use crate::audio::RecordingSink;
 
     pub const MAX_ROM_SIZE: usize = 0x1000 - 0x200;
 
@@ -54,7 +338,7 @@ mod test {
     #[should_panic]
     pub fn test_overflow_load_rom() {
         let data: Vec<u8> = vec![0; MAX_ROM_SIZE + 1];
-        let mut chip8 = Chip8::new();
+        let mut chip8 = Chip8::new(Quirks::default());
         chip8.load_rom(&data);
     }
 
@@ -62,14 +346,295 @@ mod test {
     #[should_panic]
     pub fn test_empty_load_rom() {
         let data: Vec<u8> = vec![0; 0];
-        let mut chip8 = Chip8::new();
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&data);
+    }
+
+    #[test]
+    pub fn test_beeper_follows_sound_timer() {
+        // V0 = 2, ST = V0, loop forever
+        let data: Vec<u8> = vec![0x60, 0x02, 0xF0, 0x18, 0x12, 0x04];
+        let recording = RecordingSink::new();
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.set_audio_sink(Box::new(recording.clone()));
+        chip8.load_rom(&data);
+
+        for _ in 0..2 {
+            chip8.run_instruction().unwrap();
+        }
+        assert_eq!(recording.events(), vec![true]);
+
+        chip8.tick_timers();
+        assert_eq!(recording.events(), vec![true]);
+
+        chip8.tick_timers();
+        assert_eq!(recording.events(), vec![true, false]);
+    }
+
+    #[test]
+    pub fn test_run_frame_ticks_timers_once() {
+        // V0 = 10, DT = V0, V1 = DT, loop forever
+        let data: Vec<u8> = vec![0x60, 0x0A, 0xF0, 0x15, 0xF1, 0x07, 0x12, 0x04];
+        let mut chip8 = Chip8::new(Quirks::default());
         chip8.load_rom(&data);
+
+        chip8.run_frame(2).unwrap();
+        assert_eq!(chip8.bus.get_delay_timer(), 9);
+
+        chip8.run_frame(1).unwrap();
+        assert_eq!(chip8.cpu.read_reg(1), 9);
+        assert_eq!(chip8.bus.get_delay_timer(), 8);
+
+        for _ in 0..20 {
+            chip8.run_frame(10).unwrap();
+        }
+        assert_eq!(chip8.bus.get_delay_timer(), 0);
+    }
+
+    #[test]
+    pub fn test_timers_run_while_waiting_for_key() {
+        // V0 = 10, DT = V0, V1 = K
+        let data: Vec<u8> = vec![0x60, 0x0A, 0xF0, 0x15, 0xF1, 0x0A];
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&data);
+
+        for _ in 0..4 {
+            chip8.run_frame(8).unwrap();
+        }
+
+        assert!(chip8.is_waiting_for_key());
+        assert_eq!(chip8.bus.get_delay_timer(), 6);
+    }
+
+    #[test]
+    pub fn test_xochip_memory_size() {
+        let data: Vec<u8> = vec![3; memory::XO_CHIP_SIZE - 0x200];
+        let mut chip8 = Chip8::new(Quirks::xochip());
+        chip8.load_rom(&data);
+
+        assert_eq!(chip8.bus.memory_read_byte(0xffff), 3);
+    }
+
+    #[test]
+    pub fn test_audio_pattern_forwarded() {
+        // V0 = 0x70, pitch = V0, loop forever
+        let data: Vec<u8> = vec![0x60, 0x70, 0xF0, 0x3A, 0x12, 0x04];
+        let recording = RecordingSink::new();
+        let mut chip8 = Chip8::new(Quirks::xochip());
+        chip8.set_audio_sink(Box::new(recording.clone()));
+        chip8.load_rom(&data);
+
+        chip8.run_frame(4).unwrap();
+
+        let expected = AudioPattern { pitch: 0x70, ..AudioPattern::default() };
+        assert_eq!(recording.patterns(), vec![expected]);
+    }
+
+    #[test]
+    pub fn test_builder() {
+        let chip8 = Chip8::builder()
+            .quirks(Quirks::superchip())
+            .rom(&[0x00, 0xFF])
+            .build();
+
+        assert_eq!(chip8.cpu.get_quirks(), Quirks::superchip());
+        assert_eq!(chip8.bus.memory_read_byte(cpu::PROGRAM_START + 1), 0xFF);
+    }
+
+    #[test]
+    pub fn test_save_and_load_state() {
+        // V0 = 5, I = 0x300, DT = V0, CALL 0x20A, draw the sprite at I, loop forever
+        let data: Vec<u8> = vec![0x60, 0x05, 0xA3, 0x00, 0xF0, 0x15, 0x22, 0x0A, 0x00, 0x00, 0xD0, 0x05, 0x12, 0x0C];
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&data);
+        chip8.bus.memory_write_byte(0x300, 0xF0);
+        chip8.key_down(0x7);
+        chip8.run_frame(5).unwrap();
+
+        let state = chip8.save_state();
+        let display = chip8.get_display_buffer().to_vec();
+        chip8.run_frame(3).unwrap();
+        chip8.key_up(0x7);
+        chip8.bus.memory_write_byte(0x300, 0x00);
+
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x20C);
+        assert_eq!(chip8.cpu.get_i(), 0x300);
+        assert_eq!(chip8.cpu.read_reg(0), 5);
+        assert_eq!(chip8.cpu.get_stack(), &[0x208]);
+        assert_eq!(chip8.bus.get_delay_timer(), 4);
+        assert_eq!(chip8.bus.memory_read_byte(0x300), 0xF0);
+        assert!(chip8.bus.is_key_pressed(0x7));
+        assert_eq!(chip8.get_display_buffer(), &display[..]);
+        assert_eq!(chip8.save_state(), state);
+    }
+
+    #[test]
+    pub fn test_load_state_errors_leave_machine_untouched() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0x60, 0x05]);
+        chip8.run_instruction().unwrap();
+        let state = chip8.save_state();
+
+        assert_eq!(chip8.load_state(b"junk"), Err(StateError::BadMagic));
+        assert_eq!(chip8.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+
+        // Loading stops at the stack pointer, so the rest of the state isn't needed
+        let mut bad_sp = StateWriter::new();
+        bad_sp.write_bytes(&[0; 16]);
+        bad_sp.write_u16(0);
+        bad_sp.write_u16(cpu::PROGRAM_START);
+        for _ in 0..16 {
+            bad_sp.write_u16(0);
+        }
+        bad_sp.write_u8(16);
+        assert_eq!(chip8.load_state(&bad_sp.into_bytes()), Err(StateError::InvalidValue("stack pointer")));
+
+        let xochip = Chip8::new(Quirks::xochip());
+        let expected = StateError::MemorySizeMismatch { expected: memory::SIZE, found: memory::XO_CHIP_SIZE };
+        assert_eq!(chip8.load_state(&xochip.save_state()), Err(expected));
+
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+        assert_eq!(chip8.cpu.read_reg(0), 5);
+    }
+
+    #[test]
+    pub fn test_load_state_restores_beeper() {
+        let recording = RecordingSink::new();
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.set_audio_sink(Box::new(recording.clone()));
+        chip8.bus.set_sound_timer(10);
+        chip8.tick_timers();
+        let state = chip8.save_state();
+        chip8.bus.set_sound_timer(0);
+        chip8.tick_timers();
+        assert_eq!(recording.events(), vec![true, false]);
+
+        chip8.load_state(&state).unwrap();
+        assert_eq!(recording.events(), vec![true, false, true]);
+    }
+
+    #[test]
+    pub fn test_seeded_random_is_reproducible() {
+        // V0 = random, V1 = random, loop forever
+        let data: Vec<u8> = vec![0xC0, 0xFF, 0xC1, 0xFF, 0x12, 0x04];
+        let run = |seed| {
+            let mut chip8 = Chip8::builder().rom(&data).seed(seed).build();
+            chip8.run_frame(3).unwrap();
+            (chip8.cpu.read_reg(0), chip8.cpu.read_reg(1))
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    pub fn test_load_state_restores_random() {
+        // V0 = random, loop back
+        let data: Vec<u8> = vec![0xC0, 0xFF, 0x12, 0x00];
+        let mut chip8 = Chip8::builder().rom(&data).build();
+        let state = chip8.save_state();
+        let first: Vec<u8> = (0..8).map(|_| { chip8.run_frame(2).unwrap(); chip8.cpu.read_reg(0) }).collect();
+
+        chip8.load_state(&state).unwrap();
+        let second: Vec<u8> = (0..8).map(|_| { chip8.run_frame(2).unwrap(); chip8.cpu.read_reg(0) }).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    pub fn test_vip_timing_runs_a_frame_of_cycles() {
+        // V0 += 1, loop back: 102 cycles a lap
+        let data: Vec<u8> = vec![0x70, 0x01, 0x12, 0x00];
+        let mut chip8 = Chip8::builder().rom(&data).vip_timing(true).build();
+
+        chip8.run_frame(1).unwrap();
+        assert_eq!(chip8.cpu.read_reg(0), 26);
+
+        // The 37 cycles run over are taken from the next frame
+        chip8.run_frame(1000).unwrap();
+        assert_eq!(chip8.cpu.read_reg(0), 52);
+    }
+
+    #[test]
+    pub fn test_vip_timing_savestate_keeps_cycles_run_over() {
+        let data: Vec<u8> = vec![0x70, 0x01, 0x12, 0x00];
+        let mut chip8 = Chip8::builder().rom(&data).vip_timing(true).build();
+        chip8.run_frame(1).unwrap();
+        chip8.run_frame(1).unwrap();
+        assert_eq!(chip8.cpu.read_reg(0), 52);
+
+        // 74 cycles were run over, leaving room for one lap fewer than a fresh frame
+        let mut restored = Chip8::builder().rom(&data).vip_timing(true).build();
+        restored.load_state(&chip8.save_state()).unwrap();
+        restored.run_frame(1).unwrap();
+        assert_eq!(restored.cpu.read_reg(0), 77);
+    }
+
+    #[test]
+    pub fn test_vip_timing_draws_once_per_frame() {
+        // V0 += 1, draw, loop back
+        let data: Vec<u8> = vec![0x70, 0x01, 0xD0, 0x15, 0x12, 0x00];
+        let quirks = Quirks { display_wait: false, ..Quirks::default() };
+        let mut chip8 = Chip8::builder().quirks(quirks).rom(&data).vip_timing(true).build();
+
+        for frame in 1..=5 {
+            chip8.run_frame(1000).unwrap();
+            assert_eq!(chip8.cpu.read_reg(0), frame);
+        }
+    }
+
+    /// Runs `frames` frames of a loop which counts its draws in V0
+    fn draws_per_frame(quirks: Quirks, vip_timing: bool, frames: usize) -> Vec<u8> {
+        // Draw, V0 += 1, loop back
+        let data: Vec<u8> = vec![0xD1, 0x11, 0x70, 0x01, 0x12, 0x00];
+        let mut chip8 = Chip8::builder().quirks(quirks).rom(&data).vip_timing(vip_timing).build();
+        (0..frames).map(|_| {
+            let before = chip8.cpu.read_reg(0);
+            chip8.run_frame(30).unwrap();
+            chip8.cpu.read_reg(0) - before
+        }).collect()
+    }
+
+    #[test]
+    pub fn test_display_wait_draws_once_per_frame() {
+        let quirks = Quirks { display_wait: true, ..Quirks::default() };
+        assert_eq!(draws_per_frame(quirks, false, 4), vec![1, 1, 1, 1]);
+        assert_eq!(draws_per_frame(quirks, true, 4), vec![1, 1, 1, 1]);
+    }
+
+    #[test]
+    pub fn test_step_instruction_past_waiting_draw() {
+        for vip_timing in [false, true] {
+            let mut chip8 = Chip8::builder().rom(&DRAW_TWICE_PROGRAM).vip_timing(vip_timing).build();
+            chip8.bus.set_delay_timer(5);
+            for pc in [0x202, 0x204, 0x200, 0x202] {
+                assert_eq!(chip8.step_instruction(), Ok(StepOutcome::Executed));
+                assert_eq!(chip8.cpu.get_pc(), pc);
+            }
+            assert_eq!(chip8.bus.get_delay_timer(), 3);
+        }
+    }
+
+    #[test]
+    pub fn test_savestate_keeps_display_wait() {
+        let mut chip8 = Chip8::builder().rom(&DRAW_TWICE_PROGRAM).build();
+        chip8.run_instruction().unwrap();
+
+        let mut restored = Chip8::builder().rom(&DRAW_TWICE_PROGRAM).build();
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert_eq!(restored.run_instruction(), Ok(StepOutcome::WaitingForVblank));
+    }
+
+    #[test]
+    pub fn test_no_display_wait_draws_every_lap() {
+        let quirks = Quirks { display_wait: false, ..Quirks::default() };
+        assert_eq!(draws_per_frame(quirks, false, 2), vec![10, 10]);
     }
 
     #[test]
     pub fn test_max_load_rom() {
         let data: Vec<u8> = vec![3; MAX_ROM_SIZE];
-        let mut chip8 = Chip8::new();
+        let mut chip8 = Chip8::new(Quirks::default());
         chip8.load_rom(&data);
 
         for i in 0..(MAX_ROM_SIZE) {
@@ -0,0 +1,556 @@
+//! Parsing the `chip8` and `chip8-headless` command lines, and the headless
+//! runs both of them share
+
+use crate::assembler::{load_rom, Program};
+use crate::chip8::Chip8;
+use crate::cpu::PROGRAM_START;
+use crate::display::PALETTE;
+use crate::headless::{display_to_text, HeadlessRunner, KeyScript, RunLimit};
+use crate::instruction::Syntax;
+use crate::memory;
+use crate::quirks::{InstructionSet, Quirks};
+use crate::trace::Tracer;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+usage: chip8 [run] ROM [OPTIONS]
+       chip8 disasm ROM [--octo] [--quirks NAME]
+       chip8 asm SOURCE [-o ROM] [--run] [--quirks NAME]
+       chip8 info ROM
+       chip8 help
+
+ROM is a binary ROM, or Octo source ending in .8o which is assembled first.
+
+run options:
+  --quirks NAME      vip, chip48, schip or xochip (default vip)
+  --ipf N            instructions per frame (default 8)
+  --vip-timing       run at the speed of the COSMAC VIP instead of a fixed IPF
+  --scale N          window pixels per low resolution pixel, 1 to 40 (default 10)
+  --fullscreen       borderless resizable window kept above the others
+  --palette COLOURS  2 or 4 comma separated RRGGBB colours, e.g. 000000,ffffff
+  --mute             run without sound
+  --seed N           seed CXKK so runs are reproducible
+  --trace PATH       log every instruction run to PATH
+  --debug            start paused with a debugger prompt on the terminal
+  --gdb PORT         wait for a GDB client on localhost:PORT
+  --headless         run without a window and print the final screen
+
+headless options:
+  --frames N         frames to run (default 600)
+  --cycles N         instructions to run instead of frames
+  --keys SCRIPT      keys pressed and released, e.g. 60+5,70-5 for key 5 at frame 60 to 70
+  --keys-file PATH   read the --keys script from PATH
+  --text PATH        write the final screen to PATH instead of printing it
+  --png PATH         write the final screen to PATH as a PNG
+  --expect PATH      fail unless the final screen matches the text in PATH
+
+keys:
+  1234 QWER ASDF ZXCV  CHIP-8 keypad       P  pause
+  Backspace            hold to rewind      N  advance a frame
+  F1-F10               load a savestate    I  advance an instruction
+  Shift+F1-F10         save a savestate    Tab  hold to fast forward
+  - =                  change the IPF      F12  toggle the speed indicator
+";
+
+/// Usage of `chip8-headless`, which takes the `chip8 run` options and always runs headless
+pub const HEADLESS_USAGE: &str = "\
+usage: chip8-headless ROM [OPTIONS]
+
+Takes the same options as `chip8 run --headless`, see `chip8 help`. Exits with
+0 on success, 1 if the ROM crashed or the screen didn't match --expect, and 2
+on bad arguments.
+";
+
+/// Roughly 500 instructions per second
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 8;
+
+/// 640x320 for the low resolution screen
+pub const DEFAULT_SCALE: usize = 10;
+
+/// A 2560x1280 window for the low resolution screen
+pub const MAX_SCALE: usize = 40;
+
+/// Frames run by `--headless`, ten seconds
+pub const DEFAULT_FRAMES: u64 = 600;
+
+/// Seed used by `--headless` without `--seed`, so runs are reproducible
+pub const DEFAULT_HEADLESS_SEED: u64 = 0;
+
+/// Options of `chip8 run` which only apply with `--headless`
+const HEADLESS_OPTIONS: [&str; 7] = ["--frames", "--cycles", "--keys", "--keys-file", "--text", "--png", "--expect"];
+
+/// A bad command line, reported with the usage
+#[derive(Debug, PartialEq, Eq)]
+pub struct CliError(pub String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(RunArgs),
+    Disasm(DisasmArgs),
+    Asm(AsmArgs),
+    Info(InfoArgs),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RunArgs {
+    pub rom_path: String,
+    pub quirks: Quirks,
+    pub instructions_per_frame: usize,
+    pub vip_timing: bool,
+    pub scale: usize,
+    pub fullscreen: bool,
+    pub palette: [u32; 4],
+    pub mute: bool,
+    pub seed: Option<u64>,
+    pub trace_path: Option<String>,
+    pub debug: bool,
+    pub gdb_port: Option<u16>,
+    pub headless: bool,
+    pub limit: RunLimit,
+    pub script: KeyScript,
+    pub text_path: Option<String>,
+    pub png_path: Option<String>,
+    pub expect_path: Option<String>,
+}
+
+impl RunArgs {
+
+    /// Run `rom_path` with every option left at its default
+    pub fn new(rom_path: &str) -> Self {
+        RunArgs {
+            rom_path: rom_path.to_string(),
+            quirks: Quirks::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            vip_timing: false,
+            scale: DEFAULT_SCALE,
+            fullscreen: false,
+            palette: PALETTE,
+            mute: false,
+            seed: None,
+            trace_path: None,
+            debug: false,
+            gdb_port: None,
+            headless: false,
+            limit: RunLimit::Frames(DEFAULT_FRAMES),
+            script: KeyScript::default(),
+            text_path: None,
+            png_path: None,
+            expect_path: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DisasmArgs {
+    pub rom_path: String,
+    pub syntax: Syntax,
+    pub quirks: Quirks,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AsmArgs {
+    pub source_path: String,
+    pub output_path: Option<String>,
+    pub run: bool,
+    pub quirks: Quirks,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InfoArgs {
+    pub rom_path: String,
+}
+
+/// The arguments after the program name, taken one at a time
+struct Args {
+    args: std::vec::IntoIter<String>,
+    /// Where the single positional argument ends up
+    path: Option<String>,
+}
+
+impl Args {
+
+    /// The value following `flag`
+    fn value(&mut self, flag: &str) -> Result<String, CliError> {
+        self.args.next().ok_or_else(|| CliError(format!("{} expects a value", flag)))
+    }
+
+    fn number<T: FromStr>(&mut self, flag: &str) -> Result<T, CliError> {
+        let value = self.value(flag)?;
+        value.parse().map_err(|_| CliError(format!("{} expects a number, got {:?}", flag, value)))
+    }
+
+    fn quirks(&mut self, flag: &str) -> Result<Quirks, CliError> {
+        let name = self.value(flag)?;
+        Quirks::from_preset_name(&name)
+            .ok_or_else(|| CliError(format!("unknown quirks preset {:?}, expected one of vip, chip48, schip or xochip", name)))
+    }
+
+    /// Take a positional argument, or reject an unknown option
+    fn positional(&mut self, arg: String) -> Result<(), CliError> {
+        if arg.starts_with('-') && arg.len() > 1 {
+            return Err(CliError(format!("unknown option {}", arg)));
+        }
+        if self.path.is_some() {
+            return Err(CliError(format!("unexpected argument {:?}", arg)));
+        }
+        self.path = Some(arg);
+        Ok(())
+    }
+
+    fn path(&mut self, what: &str) -> Result<String, CliError> {
+        self.path.take().ok_or_else(|| CliError(format!("no {} given", what)))
+    }
+}
+
+impl Iterator for Args {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.args.next()
+    }
+}
+
+/// Parse the arguments after the program name
+pub fn parse(args: Vec<String>) -> Result<Command, CliError> {
+    let subcommand = args.first().cloned();
+    let mut args = Args { args: args.into_iter(), path: None };
+    if let Some("run") | Some("disasm") | Some("asm") | Some("info") | Some("help") = subcommand.as_deref() {
+        args.next();
+    }
+
+    match subcommand.as_deref() {
+        Some("disasm") => parse_disasm(&mut args),
+        Some("asm") => parse_asm(&mut args),
+        Some("info") => parse_info(&mut args),
+        Some("help") | Some("--help") | Some("-h") | None => Ok(Command::Help),
+        _ => parse_run(&mut args, false).map(Command::Run),
+    }
+}
+
+/// Parse the arguments after the program name of `chip8-headless`
+pub fn parse_headless(args: Vec<String>) -> Result<RunArgs, CliError> {
+    parse_run(&mut Args { args: args.into_iter(), path: None }, true)
+}
+
+fn parse_run(args: &mut Args, headless: bool) -> Result<RunArgs, CliError> {
+    let mut run = RunArgs::new("");
+    run.headless = headless;
+    // The first option given which only applies to headless runs
+    let mut headless_option = None;
+    while let Some(arg) = args.next() {
+        if HEADLESS_OPTIONS.contains(&arg.as_str()) && headless_option.is_none() {
+            headless_option = Some(arg.clone());
+        }
+        match arg.as_str() {
+            "--quirks" => run.quirks = args.quirks(&arg)?,
+            "--ipf" => run.instructions_per_frame = args.number(&arg)?,
+            "--vip-timing" => run.vip_timing = true,
+            "--scale" => run.scale = args.number(&arg)?,
+            "--fullscreen" => run.fullscreen = true,
+            "--palette" => run.palette = parse_palette(&args.value(&arg)?)?,
+            "--mute" => run.mute = true,
+            "--seed" => run.seed = Some(args.number(&arg)?),
+            "--trace" => run.trace_path = Some(args.value(&arg)?),
+            "--debug" => run.debug = true,
+            "--gdb" => run.gdb_port = Some(args.number(&arg)?),
+            "--headless" => run.headless = true,
+            "--frames" => run.limit = RunLimit::Frames(args.number(&arg)?),
+            "--cycles" => run.limit = RunLimit::Cycles(args.number(&arg)?),
+            "--keys" => run.script = parse_script(&args.value(&arg)?)?,
+            "--keys-file" => {
+                let path = args.value(&arg)?;
+                let script = fs::read_to_string(&path).map_err(|e| CliError(format!("unable to read {}: {}", path, e)))?;
+                run.script = parse_script(&script)?;
+            }
+            "--text" => run.text_path = Some(args.value(&arg)?),
+            "--png" => run.png_path = Some(args.value(&arg)?),
+            "--expect" => run.expect_path = Some(args.value(&arg)?),
+            _ => args.positional(arg)?,
+        }
+    }
+    run.rom_path = args.path("ROM")?;
+
+    if run.instructions_per_frame == 0 {
+        return Err(CliError("--ipf must be at least 1".to_string()));
+    }
+    if !(1..=MAX_SCALE).contains(&run.scale) {
+        return Err(CliError(format!("--scale must be from 1 to {}", MAX_SCALE)));
+    }
+    if !run.headless {
+        if let Some(option) = headless_option {
+            return Err(CliError(format!("{} only applies to --headless runs", option)));
+        }
+    }
+    if run.headless && (run.debug || run.gdb_port.is_some()) {
+        return Err(CliError("--debug and --gdb need a window, they can't be used with --headless".to_string()));
+    }
+    if run.headless && run.seed.is_none() {
+        run.seed = Some(DEFAULT_HEADLESS_SEED);
+    }
+    Ok(run)
+}
+
+fn parse_script(script: &str) -> Result<KeyScript, CliError> {
+    KeyScript::parse(script).map_err(|e| CliError(e.to_string()))
+}
+
+fn parse_disasm(args: &mut Args) -> Result<Command, CliError> {
+    let mut syntax = Syntax::Cowgod;
+    let mut quirks = Quirks::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            "--quirks" => quirks = args.quirks(&arg)?,
+            _ => args.positional(arg)?,
+        }
+    }
+    Ok(Command::Disasm(DisasmArgs { rom_path: args.path("ROM")?, syntax, quirks }))
+}
+
+fn parse_asm(args: &mut Args) -> Result<Command, CliError> {
+    let mut output_path = None;
+    let mut run = false;
+    let mut quirks = Quirks::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output_path = Some(args.value(&arg)?),
+            "--run" => run = true,
+            "--quirks" => quirks = args.quirks(&arg)?,
+            _ => args.positional(arg)?,
+        }
+    }
+    Ok(Command::Asm(AsmArgs { source_path: args.path("source file")?, output_path, run, quirks }))
+}
+
+fn parse_info(args: &mut Args) -> Result<Command, CliError> {
+    while let Some(arg) = args.next() {
+        args.positional(arg)?;
+    }
+    Ok(Command::Info(InfoArgs { rom_path: args.path("ROM")? }))
+}
+
+/// Two colours replace the background and foreground, four replace every XO-CHIP plane combination
+fn parse_palette(value: &str) -> Result<[u32; 4], CliError> {
+    let colours = value.split(',')
+        .map(|colour| {
+            let hex = colour.trim().trim_start_matches('#');
+            match u32::from_str_radix(hex, 16) {
+                Ok(rgb) if hex.len() == 6 => Ok(rgb),
+                _ => Err(CliError(format!("--palette expects RRGGBB colours, got {:?}", colour))),
+            }
+        })
+        .collect::<Result<Vec<u32>, CliError>>()?;
+
+    let mut palette = PALETTE;
+    match colours.len() {
+        2 | 4 => palette[..colours.len()].copy_from_slice(&colours),
+        count => return Err(CliError(format!("--palette expects 2 or 4 colours, got {}", count))),
+    }
+    Ok(palette)
+}
+
+/// Load the ROM to run, assembling Octo source first
+pub fn load_program(args: &RunArgs) -> Result<Program, Box<dyn Error>> {
+    load_rom(Path::new(&args.rom_path)).map_err(|e| format!("unable to load {}: {}", args.rom_path, e).into())
+}
+
+/// Create the machine for a run, rejecting ROMs which `Chip8::load_rom` would panic on
+pub fn build_chip8(program: &Program, args: &RunArgs) -> Result<Chip8, Box<dyn Error>> {
+    let memory_size = if args.quirks.instruction_set >= InstructionSet::XoChip { memory::XO_CHIP_SIZE } else { memory::SIZE };
+    let space = memory_size - PROGRAM_START as usize;
+    if program.rom.is_empty() {
+        return Err(format!("{} is empty", args.rom_path).into());
+    }
+    if program.rom.len() > space {
+        return Err(format!("{} is {} bytes, only {} fit in memory", args.rom_path, program.rom.len(), space).into());
+    }
+
+    let mut builder = Chip8::builder().quirks(args.quirks).rom(&program.rom).vip_timing(args.vip_timing);
+    if let Some(seed) = args.seed {
+        builder = builder.seed(seed);
+    }
+    let mut chip8 = builder.build();
+    if let Some(path) = &args.trace_path {
+        let file = File::create(path).map_err(|e| format!("unable to create {}: {}", path, e))?;
+        chip8.set_tracer(Tracer::new(Box::new(BufWriter::new(file))));
+    }
+    Ok(chip8)
+}
+
+/// Flush the instruction log, if there is one
+pub fn finish_trace(chip8: &mut Chip8) -> Result<(), Box<dyn Error>> {
+    if let Some(tracer) = chip8.take_tracer() {
+        tracer.finish().map_err(|e| format!("unable to write trace: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Run without a window until the frame or cycle limit, then print or save the
+/// screen. Fails if the program crashed or the screen doesn't match `--expect`
+pub fn run_headless(program: &Program, args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    let mut chip8 = build_chip8(program, args)?;
+    let mut runner = HeadlessRunner::new(args.limit, args.instructions_per_frame);
+    runner.script = args.script.clone();
+    let result = runner.run(&mut chip8);
+    if let Ok(summary) = &result {
+        eprintln!(
+            "Ran {} frames, {} instructions{}",
+            summary.frames,
+            summary.cycles,
+            if summary.exited { ", program exited" } else { "" }
+        );
+    }
+
+    // The screen is dumped even after a crash, to show where it happened
+    let text = display_to_text(&chip8);
+    match &args.text_path {
+        Some(path) => fs::write(path, &text).map_err(|e| format!("unable to write {}: {}", path, e))?,
+        None if args.png_path.is_none() && args.expect_path.is_none() => print!("{}", text),
+        None => {}
+    }
+    if let Some(path) = &args.png_path {
+        save_png(&chip8, &args.palette, path).map_err(|e| format!("unable to write {}: {}", path, e))?;
+    }
+    finish_trace(&mut chip8)?;
+    result.map_err(|e| format!("CHIP-8 crashed: {}", e))?;
+
+    if let Some(path) = &args.expect_path {
+        let expected = fs::read_to_string(path).map_err(|e| format!("unable to read {}: {}", path, e))?;
+        if expected != text {
+            return Err(format!("screen does not match {}", path).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "png")]
+fn save_png(chip8: &Chip8, palette: &[u32; 4], path: &str) -> std::io::Result<()> {
+    crate::headless::write_png(chip8, palette, BufWriter::new(File::create(path)?))
+}
+
+#[cfg(not(feature = "png"))]
+fn save_png(_chip8: &Chip8, _palette: &[u32; 4], _path: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Other, "built without the png feature"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Command, CliError> {
+        parse(line.split_whitespace().map(String::from).collect())
+    }
+
+    #[test]
+    pub fn test_run() {
+        let mut expected = RunArgs::new("pong.ch8");
+        assert_eq!(parse_line("pong.ch8"), Ok(Command::Run(RunArgs::new("pong.ch8"))));
+        assert_eq!(parse_line("run pong.ch8"), Ok(Command::Run(RunArgs::new("pong.ch8"))));
+
+        expected.quirks = Quirks::chip48();
+        expected.instructions_per_frame = 20;
+        expected.scale = 5;
+        expected.seed = Some(7);
+        expected.mute = true;
+        expected.fullscreen = true;
+        expected.palette = [0x101010, 0xf0f0f0, PALETTE[2], PALETTE[3]];
+        expected.trace_path = Some("trace.log".to_string());
+        assert_eq!(
+            parse_line("--ipf 20 pong.ch8 --quirks chip48 --scale 5 --seed 7 --mute --fullscreen --palette 101010,#F0F0F0 --trace trace.log"),
+            Ok(Command::Run(expected))
+        );
+    }
+
+    #[test]
+    pub fn test_headless() {
+        let options = "pong.ch8 --cycles 100 --keys 60+5,70-5 --text screen.txt";
+        let mut expected = RunArgs::new("pong.ch8");
+        expected.headless = true;
+        expected.seed = Some(DEFAULT_HEADLESS_SEED);
+        expected.limit = RunLimit::Cycles(100);
+        expected.script = KeyScript::parse("60+5 70-5").unwrap();
+        expected.text_path = Some("screen.txt".to_string());
+        assert_eq!(parse_headless(options.split_whitespace().map(String::from).collect()), Ok(expected));
+
+        let run = match parse_line(&format!("run --headless {} --seed 3", options)) {
+            Ok(Command::Run(run)) => run,
+            other => panic!("parsed as {:?}", other),
+        };
+        assert_eq!(run.seed, Some(3));
+        assert_eq!(run.limit, RunLimit::Cycles(100));
+    }
+
+    #[test]
+    pub fn test_run_headless_errors() {
+        let mut args = RunArgs::new("empty.ch8");
+        args.headless = true;
+        let program = Program { rom: Vec::new(), labels: Default::default(), breakpoints: Default::default() };
+        assert_eq!(run_headless(&program, &args).unwrap_err().to_string(), "empty.ch8 is empty");
+
+        // Return with an empty stack
+        let program = Program { rom: vec![0x00, 0xEE], ..program };
+        args.rom_path = "crash.ch8".to_string();
+        args.text_path = Some(std::env::temp_dir().join("chip8-cli-crash.txt").display().to_string());
+        assert_eq!(run_headless(&program, &args).unwrap_err().to_string(), "CHIP-8 crashed: stack underflow at 0x200");
+    }
+
+    #[test]
+    pub fn test_subcommands() {
+        assert_eq!(parse_line(""), Ok(Command::Help));
+        assert_eq!(parse_line("help"), Ok(Command::Help));
+        assert_eq!(
+            parse_line("disasm --octo pong.ch8"),
+            Ok(Command::Disasm(DisasmArgs { rom_path: "pong.ch8".to_string(), syntax: Syntax::Octo, quirks: Quirks::default() }))
+        );
+        assert_eq!(
+            parse_line("asm game.8o -o game.ch8 --run"),
+            Ok(Command::Asm(AsmArgs {
+                source_path: "game.8o".to_string(),
+                output_path: Some("game.ch8".to_string()),
+                run: true,
+                quirks: Quirks::default(),
+            }))
+        );
+        assert_eq!(parse_line("info pong.ch8"), Ok(Command::Info(InfoArgs { rom_path: "pong.ch8".to_string() })));
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let error = |line| match parse_line(line) {
+            Err(CliError(message)) => message,
+            Ok(command) => panic!("{:?} parsed as {:?}", line, command),
+        };
+        assert_eq!(error("--mute"), "no ROM given");
+        assert_eq!(error("a.ch8 b.ch8"), "unexpected argument \"b.ch8\"");
+        assert_eq!(error("a.ch8 --speed 2"), "unknown option --speed");
+        assert_eq!(error("a.ch8 --ipf"), "--ipf expects a value");
+        assert_eq!(error("a.ch8 --ipf fast"), "--ipf expects a number, got \"fast\"");
+        assert_eq!(error("a.ch8 --ipf 0"), "--ipf must be at least 1");
+        assert_eq!(error("a.ch8 --quirks snes"), "unknown quirks preset \"snes\", expected one of vip, chip48, schip or xochip");
+        assert_eq!(error("a.ch8 --palette 000000"), "--palette expects 2 or 4 colours, got 1");
+        assert_eq!(error("a.ch8 --palette 000000,white"), "--palette expects RRGGBB colours, got \"white\"");
+        assert_eq!(error("a.ch8 --scale 0"), "--scale must be from 1 to 40");
+        assert_eq!(error("a.ch8 --scale 100000"), "--scale must be from 1 to 40");
+        assert_eq!(error("a.ch8 --frames 60"), "--frames only applies to --headless runs");
+        assert_eq!(error("a.ch8 --png a.png --text a.txt"), "--png only applies to --headless runs");
+        assert_eq!(error("a.ch8 --headless --keys 60*5"), "invalid key event \"60*5\", expected FRAME+KEY or FRAME-KEY");
+        assert_eq!(error("a.ch8 --headless --debug"), "--debug and --gdb need a window, they can't be used with --headless");
+        assert_eq!(error("asm"), "no source file given");
+        assert_eq!(error("info"), "no ROM given");
+    }
+}
@@ -1,288 +1,446 @@
 use crate::bus::Bus;
+use crate::instruction;
+use crate::instruction::Instruction;
+use crate::memory;
+use crate::quirks::{IndexIncrement, InstructionSet, Quirks};
+use crate::rng::Xorshift;
+use crate::state::{StateError, StateReader, StateWriter};
 
-use rand::Rng;
+use std::error::Error;
+use std::fmt;
 
 pub const PROGRAM_START: u16 = 0x200;
 
+/// What happened after a single instruction was run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction was executed normally
+    Executed,
+    /// FX0A is halting the CPU until a key is pressed and released
+    WaitingForKey,
+    /// DXYN is waiting for the next frame before drawing
+    WaitingForVblank,
+    /// The program ran the SUPER-CHIP exit instruction
+    Exited,
+}
+
+/// A program that draws twice in a row and loops back, so the second draw waits for the display
+#[cfg(test)]
+pub(crate) const DRAW_TWICE_PROGRAM: [u8; 6] = [0xD0, 0x11, 0xD0, 0x11, 0x12, 0x00];
+
+/// Progress of an FX0A instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyWait {
+    /// Not executing FX0A
+    Idle,
+    /// Waiting for any key to be pressed
+    Press,
+    /// Waiting for the pressed key to be released
+    Release(u8),
+}
+
+/// Errors that stop the CPU from executing any further
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    /// The opcode at `pc` is not a known instruction
+    InvalidOpcode { pc: u16, opcode: u16 },
+    /// A subroutine call at `pc` exceeded the stack depth
+    StackOverflow { pc: u16 },
+    /// A return at `pc` was executed with an empty stack
+    StackUnderflow { pc: u16 },
+    /// The program counter points outside of memory
+    PcOutOfBounds { pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::InvalidOpcode { pc, opcode } => write!(f, "invalid opcode {:#06x} at {:#05x}", opcode, pc),
+            CpuError::StackOverflow { pc } => write!(f, "stack overflow at {:#05x}", pc),
+            CpuError::StackUnderflow { pc } => write!(f, "stack underflow at {:#05x}", pc),
+            CpuError::PcOutOfBounds { pc } => write!(f, "program counter out of bounds ({:#06x})", pc),
+        }
+    }
+}
+
+impl Error for CpuError {}
+
 pub struct Cpu {
     v: [u8; 16],
     i: u16,
     pc: u16,
     stack: [u16; 16],
     sp: u8,
-    rng: rand::rngs::ThreadRng,
+    rng: Xorshift,
+    rpl: [u8; 16],
+    quirks: Quirks,
+    key_wait: KeyWait,
+    exited: bool,
 }
 
 impl Cpu {
-    pub fn new() -> Self {
+    /// Create a CPU with a randomly seeded CXKK generator
+    pub fn new(quirks: Quirks) -> Self {
+        Cpu::with_rng(quirks, Xorshift::from_entropy())
+    }
+
+    /// Create a CPU drawing CXKK values from `rng`, e.g. a seeded generator for reproducible runs
+    pub fn with_rng(quirks: Quirks, rng: Xorshift) -> Self {
         Cpu {
             v: [0; 16],
             i: 0,
             pc: PROGRAM_START,
             stack: [0; 16],
             sp: 0,
-            rng: rand::thread_rng()
+            rng,
+            rpl: [0; 16],
+            quirks,
+            key_wait: KeyWait::Idle,
+            exited: false
         }
     }
 
-    pub fn run_instruction(&mut self, bus: &mut Bus) {
+    /// Whether the instruction at the program counter is a DXYN which will wait for the next frame
+    pub(crate) fn waits_for_vblank(&self, bus: &Bus) -> bool {
+        if !self.quirks.display_wait || bus.has_vblank() {
+            return false;
+        }
+        let opcode = (bus.memory_peek_byte(self.pc) as u16) << 8 | bus.memory_peek_byte(self.pc.wrapping_add(1)) as u16;
+        matches!(instruction::decode_for(opcode, self.quirks.instruction_set), Some(Instruction::Draw(..)))
+    }
 
-        let lo = bus.memory_read_byte(self.pc) as u16;
-        let hi = bus.memory_read_byte(self.pc + 1) as u16;
-        let instruction: u16 = (lo << 8) | hi;
+    /// Fetch, decode and execute the instruction at the program counter
+    pub fn run_instruction(&mut self, bus: &mut Bus) -> Result<StepOutcome, CpuError> {
 
-        let nnn: u16 = instruction & 0xfff;
-        let n: u8 = (instruction & 0xf) as u8;
-        let x: u8 = ((instruction & 0x0f00) >> 8) as u8;
-        let y: u8 = ((instruction & 0x00f0) >> 4) as u8;
-        let kk: u8 = (instruction & 0xff) as u8;
+        if self.pc as usize + 1 >= bus.memory_get_size() {
+            return Err(CpuError::PcOutOfBounds { pc: self.pc });
+        }
 
-        match (instruction >> 12) & 0xf {
-            0x0 => {
-                match instruction & 0xfff {
-                    // Clear the display
-                    0x0e0 => {
-                        bus.clear_screen();
-                        self.pc += 2;
-                    }
-                    // Returns from a subroutine
-                    0x0ee => {
-                        self.pc = self.stack[self.sp as usize];
-                        self.sp -= 1;
-                    }
-                    _ => {
-                        self.pc = nnn;
-                    }
-                }
+        let lo = bus.memory_peek_byte(self.pc) as u16;
+        let hi = bus.memory_peek_byte(self.pc + 1) as u16;
+        let opcode: u16 = (lo << 8) | hi;
 
-            // Jump to location nnn
+        let instruction = match instruction::decode_for(opcode, self.quirks.instruction_set) {
+            Some(instruction) => instruction,
+            None => return Err(CpuError::InvalidOpcode { pc: self.pc, opcode }),
+        };
+
+        // Checked before anything changes, so an instruction running off the end of memory leaves the machine untouched
+        let next_pc = match instruction {
+            Instruction::Sys(_) | Instruction::Jump(_) | Instruction::JumpOffset(_) | Instruction::Return | Instruction::Exit => self.pc,
+            _ => self.pc_after(instruction.size())?,
+        };
+
+        match instruction {
+            // Jump to location nnn, machine code routines can't be run
+            Instruction::Sys(nnn) => {
+                self.pc = nnn;
+            }
+            // Scroll the display down n pixels
+            Instruction::ScrollDown(n) => {
+                bus.scroll_down(n as usize);
+                self.pc = next_pc;
+            }
+            // Scroll the display up n pixels
+            Instruction::ScrollUp(n) => {
+                bus.scroll_up(n as usize);
+                self.pc = next_pc;
             }
-            0x1 => {
+            // Clear the display
+            Instruction::Clear => {
+                bus.clear_screen();
+                self.pc = next_pc;
+            }
+            // Returns from a subroutine
+            Instruction::Return => {
+                if self.sp == 0 {
+                    return Err(CpuError::StackUnderflow { pc: self.pc });
+                }
+                self.pc = self.stack[self.sp as usize];
+                self.sp -= 1;
+            }
+            // Scroll the display right 4 pixels
+            Instruction::ScrollRight => {
+                bus.scroll_right(4);
+                self.pc = next_pc;
+            }
+            // Scroll the display left 4 pixels
+            Instruction::ScrollLeft => {
+                bus.scroll_left(4);
+                self.pc = next_pc;
+            }
+            // Exit the interpreter
+            Instruction::Exit => {
+                self.exited = true;
+                return Ok(StepOutcome::Exited);
+            }
+            // Switch to the low resolution screen
+            Instruction::Lores => {
+                bus.set_hires(false);
+                self.pc = next_pc;
+            }
+            // Switch to the high resolution screen
+            Instruction::Hires => {
+                bus.set_hires(true);
+                self.pc = next_pc;
+            }
+            // Jump to location nnn
+            Instruction::Jump(nnn) => {
                 self.pc = nnn;
             }
             // Call subroutine at nnn
-            0x2 => {
+            Instruction::Call(nnn) => {
+                if self.sp as usize + 1 >= self.stack.len() {
+                    return Err(CpuError::StackOverflow { pc: self.pc });
+                }
                 self.sp += 1;
-                self.stack[self.sp as usize] = self.pc + 2;
+                self.stack[self.sp as usize] = next_pc;
                 self.pc = nnn;
             }
             // Skip next instruction if Vx = kk
-            0x3 => {
-                if self.read_reg(x) == kk {
-                    self.pc += 4;
-                }
-                else {
-                    self.pc += 2;
-                }
+            Instruction::SkipEqualByte(x, kk) => {
+                self.skip_if(self.read_reg(x) == kk, next_pc, bus)?;
             }
             // Skip next instruction if Vx != kk
-            0x4 => {
-                if self.read_reg(x) != kk {
-                    self.pc += 4;
-                }
-                else {
-                    self.pc += 2;
-                }
+            Instruction::SkipNotEqualByte(x, kk) => {
+                self.skip_if(self.read_reg(x) != kk, next_pc, bus)?;
             }
-            // Skip next instruction if Vx == Vx
-            0x5 => {
-                assert_eq!(0, n);
-                if self.read_reg(x) == self.read_reg(y) {
-                    self.pc += 4;
+            // Skip next instruction if Vx == Vy
+            Instruction::SkipEqual(x, y) => {
+                self.skip_if(self.read_reg(x) == self.read_reg(y), next_pc, bus)?;
+            }
+            // Store registers Vx through Vy in memory starting at location I
+            Instruction::SaveRange(x, y) => {
+                for (offset, index) in Cpu::register_range(x, y).enumerate() {
+                    bus.memory_write_byte(self.i.wrapping_add(offset as u16), self.read_reg(index));
                 }
-                else {
-                    self.pc += 2;
+                self.pc = next_pc;
+            }
+            // Read registers Vx through Vy from memory starting at location I
+            Instruction::LoadRange(x, y) => {
+                for (offset, index) in Cpu::register_range(x, y).enumerate() {
+                    self.write_reg(index, bus.memory_read_byte(self.i.wrapping_add(offset as u16)));
                 }
+                self.pc = next_pc;
             }
             // Set Vx = kk
-            0x6 => {
+            Instruction::LoadByte(x, kk) => {
                 self.write_reg(x, kk);
-                self.pc += 2;
+                self.pc = next_pc;
             }
             // Set Vx = Vx + kk
-            0x7 => {
+            Instruction::AddByte(x, kk) => {
                 let (value, _) = self.read_reg(x).overflowing_add(kk);
                 self.write_reg(x, value);
-                self.pc += 2;
-            }
-            0x8 => {
-                match instruction & 0xf {
-                    // Set Vx = Vy
-                    0x0 => {
-                        self.write_reg(x, self.read_reg(y));
-                        self.pc += 2;
-                    }
-                    // Set Vx = Vx | Vy
-                    0x1 => {
-                        self.write_reg(x, self.read_reg(x) | self.read_reg(y));
-                        self.pc += 2;
-                    }
-                    // Set Vx = Vx & Vy
-                    0x2 => {
-                        self.write_reg(x, self.read_reg(x) & self.read_reg(y));
-                        self.pc += 2;
-                    }
-                    // Set Vx ^ Vy
-                    0x3 => {
-                        self.write_reg(x, self.read_reg(x) ^ self.read_reg(y));
-                        self.pc += 2;
-                    }
-                    // Set Vx = Vx + Vy, set VF = carry
-                    0x4 => {
-                        let (value, flag) = self.read_reg(x).overflowing_add(self.read_reg(y));
-                        self.write_reg(x, value);
-                        self.write_flag_reg(if flag {1} else {0});
-                        self.pc += 2;
-                    }
-                    // Set Vx = Vx - Vy, set VF = NOT borrow
-                    0x5 => {
-                        let (value, flag) = self.read_reg(x).overflowing_sub(self.read_reg(y));
-                        self.write_reg(x, value);
-                        self.write_flag_reg(if flag {0} else {1});
-                        self.pc += 2;
-                    }
-                    // Set Vx = Vx SHR 1
-                    0x6 => {
-                        self.write_flag_reg(self.read_reg(x) & 0x1);
-                        self.write_reg(x, self.read_reg(x) >> 1);
-                        self.pc += 2;
-                    }
-                    // Set Vx = Vy - Vx, set VF = NOT borrow
-                    0x7 => {
-                        let (value, _) = self.read_reg(y).overflowing_sub(self.read_reg(x));
-                        self.write_flag_reg(if self.read_reg(y) > self.read_reg(x) {1} else {0});
-                        self.write_reg(x, value);
-                        self.pc += 2;
-                    }
-                    // Set Vx = Vx SHL 1
-                    0xE => {
-                        self.write_flag_reg(if (self.read_reg(x) & 0x80) >> 7 == 1 {1} else {0});
-                        self.write_reg(x, self.read_reg(x) << 1);
-                        self.pc += 2;
-                    }
-                    _ => unreachable!()
-                }
+                self.pc = next_pc;
+            }
+            // Set Vx = Vy
+            Instruction::Move(x, y) => {
+                self.write_reg(x, self.read_reg(y));
+                self.pc = next_pc;
+            }
+            // Set Vx = Vx | Vy
+            Instruction::Or(x, y) => {
+                self.write_reg(x, self.read_reg(x) | self.read_reg(y));
+                self.reset_flag_reg();
+                self.pc = next_pc;
+            }
+            // Set Vx = Vx & Vy
+            Instruction::And(x, y) => {
+                self.write_reg(x, self.read_reg(x) & self.read_reg(y));
+                self.reset_flag_reg();
+                self.pc = next_pc;
+            }
+            // Set Vx ^ Vy
+            Instruction::Xor(x, y) => {
+                self.write_reg(x, self.read_reg(x) ^ self.read_reg(y));
+                self.reset_flag_reg();
+                self.pc = next_pc;
+            }
+            // Set Vx = Vx + Vy, set VF = carry
+            Instruction::Add(x, y) => {
+                let (value, flag) = self.read_reg(x).overflowing_add(self.read_reg(y));
+                self.write_reg(x, value);
+                self.write_flag_reg(if flag {1} else {0});
+                self.pc = next_pc;
+            }
+            // Set Vx = Vx - Vy, set VF = NOT borrow
+            Instruction::Sub(x, y) => {
+                let (value, flag) = self.read_reg(x).overflowing_sub(self.read_reg(y));
+                self.write_reg(x, value);
+                self.write_flag_reg(if flag {0} else {1});
+                self.pc = next_pc;
+            }
+            // Set Vx = Vx SHR 1
+            Instruction::ShiftRight(x, y) => {
+                let value = self.read_shift_source(x, y);
+                self.write_reg(x, value >> 1);
+                self.write_flag_reg(value & 0x1);
+                self.pc = next_pc;
+            }
+            // Set Vx = Vy - Vx, set VF = NOT borrow
+            Instruction::SubReverse(x, y) => {
+                let (value, flag) = self.read_reg(y).overflowing_sub(self.read_reg(x));
+                self.write_reg(x, value);
+                self.write_flag_reg(if flag {0} else {1});
+                self.pc = next_pc;
+            }
+            // Set Vx = Vx SHL 1
+            Instruction::ShiftLeft(x, y) => {
+                let value = self.read_shift_source(x, y);
+                self.write_reg(x, value << 1);
+                self.write_flag_reg((value & 0x80) >> 7);
+                self.pc = next_pc;
             }
             // Skip next instruction if Vx != Vy
-            0x9 => {
-                assert_eq!(0, n);
-                if self.read_reg(x) != self.read_reg(y) {
-                    self.pc += 4;
-                }
-                else {
-                    self.pc += 2;
-                }
+            Instruction::SkipNotEqual(x, y) => {
+                self.skip_if(self.read_reg(x) != self.read_reg(y), next_pc, bus)?;
             }
             // Set I = nnn
-            0xA => {
+            Instruction::LoadIndex(nnn) => {
                 self.i = nnn;
-                self.pc += 2;
+                self.pc = next_pc;
             }
-            // Jump to location nnn + V0
-            0xB => {
-                self.pc = nnn + self.read_reg(0) as u16;
+            // Jump to location nnn + V0 (or xnn + Vx)
+            Instruction::JumpOffset(nnn) => {
+                let x = (nnn >> 8) as u8;
+                let offset = if self.quirks.jump_uses_vx { self.read_reg(x) } else { self.read_reg(0) };
+                self.pc = nnn + offset as u16;
             }
             // Set Vx = random byte AND kk
-            0xC => {
-                let random_value: u8 = self.rng.gen_range(0..0xff);
+            Instruction::Random(x, kk) => {
+                let random_value = self.rng.next_byte();
                 self.write_reg(x, random_value & kk);
-                self.pc += 2;
+                self.pc = next_pc;
             }
-            // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision
-            0xD => {
+            // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+            // On SUPER-CHIP, n = 0 displays a 16x16 sprite
+            Instruction::Draw(x, y, n) => {
+                if self.quirks.display_wait && !bus.take_vblank() {
+                    return Ok(StepOutcome::WaitingForVblank);
+                }
                 self.draw_sprite(bus, self.read_reg(x), self.read_reg(y), n);
-                self.pc += 2;
-            }
-            0xE => {
-                match hi {
-                    // Skip next instruction if key with value of Vx is pressed
-                    0x9e => {
-                        let key = self.read_reg(x);
-                        if bus.is_key_pressed(key) {
-                            self.pc += 4;
-                        }
-                        else {
-                            self.pc += 2;
-                        }
-                    }
-                    // Skip the next instruction if the key with the value of Vx is not pressed
-                    0xA1 => {
-                        let key = self.read_reg(x);
-                        if !bus.is_key_pressed(key) {
-                            self.pc += 4;
-                        }
-                        else {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => unreachable!()
+                self.pc = next_pc;
+            }
+            // Skip next instruction if key with value of Vx is pressed
+            Instruction::SkipKeyPressed(x) => {
+                self.skip_if(bus.is_key_pressed(self.read_reg(x)), next_pc, bus)?;
+            }
+            // Skip the next instruction if the key with the value of Vx is not pressed
+            Instruction::SkipKeyNotPressed(x) => {
+                self.skip_if(!bus.is_key_pressed(self.read_reg(x)), next_pc, bus)?;
+            }
+            // Set I = the 16 bit address nnnn stored after the instruction
+            Instruction::LoadLongIndex => {
+                let address_hi = bus.memory_peek_byte(self.pc.wrapping_add(2)) as u16;
+                let address_lo = bus.memory_peek_byte(self.pc.wrapping_add(3)) as u16;
+                self.i = (address_hi << 8) | address_lo;
+                self.pc = next_pc;
+            }
+            // Select the drawing planes n
+            Instruction::SelectPlanes(n) => {
+                bus.set_planes(n);
+                self.pc = next_pc;
+            }
+            // Load the 16 byte audio pattern starting at location I
+            Instruction::LoadAudioPattern => {
+                let mut pattern = [0; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = bus.memory_read_byte(self.i.wrapping_add(offset as u16));
                 }
+                bus.set_audio_pattern(pattern);
+                self.pc = next_pc;
             }
-            0xF => {
-                match hi {
-                    // Set Vx = delay timer value
-                    0x07 => {
-                        self.write_reg(x, bus.get_delay_timer());
-                        self.pc += 2;
-                    }
-                    // Wait for a key press, store the value of the key in Vx
-                    0x0A => {
-                        if let Some(val) = bus.get_key_pressed() {
-                            self.write_reg(x, val);
-                        }
-                        self.pc += 2;
-                    }
-                    // Set delay timer = Vx
-                    0x15 => {
-                        bus.set_delay_timer(self.read_reg(x));
-                        self.pc += 2;
-                    }
-                    // Set sound timer = Vx
-                    0x18 => {
-                        // TODO Sound Timer
-                        self.pc += 2;
-                    }
-                    // Set I = I + Vx
-                    0x1E => {
-                        self.i = self.i + self.read_reg(x) as u16;
-                        self.pc += 2;
-                    }
-                    // Set I = location of sprite for digit Vx
-                    0x29 => {
-                        self.i = self.read_reg(x) as u16 * 5;
-                        self.pc += 2;
-                    }
-                    // Store BCD representation of Vx in memory locations I, I+1, I+2
-                    0x33 => {
-                        let val = self.read_reg(x);
-                        bus.memory_write_byte(self.i, val / 100);
-                        bus.memory_write_byte(self.i + 1, (val % 100) / 10);
-                        bus.memory_write_byte(self.i + 2, val % 10);
-                        self.pc += 2;
-                    }
-                    // Store registers V0 through Vx in memory starting at location I
-                    0x55 => {
-                        for index in 0..=x {
-                            bus.memory_write_byte(self.i + index as u16, self.read_reg(index));
-                        }
-                        self.i += x as u16 + 1;
-                        self.pc += 2;
-                    }
-                    // Read registers V0 through Vx from memory starting at location I
-                    0x65 => {
-                        for index in 0..=x {
-                            self.write_reg(index, bus.memory_read_byte(self.i + index as u16))
-                        }
-                        self.pc += 2;
+            // Set Vx = delay timer value
+            Instruction::GetDelay(x) => {
+                self.write_reg(x, bus.get_delay_timer());
+                self.pc = next_pc;
+            }
+            // Wait for a key press and release, store the value of the key in Vx
+            Instruction::WaitKey(x) => {
+                self.key_wait = match self.key_wait {
+                    KeyWait::Idle | KeyWait::Press => match bus.get_key_pressed() {
+                        Some(key) => KeyWait::Release(key),
+                        None => KeyWait::Press,
+                    },
+                    KeyWait::Release(key) if !bus.is_key_pressed(key) => {
+                        self.write_reg(x, key);
+                        KeyWait::Idle
                     }
-                    _ => unreachable!("Instruction: {:#x}", instruction)
+                    waiting => waiting,
+                };
+                if self.key_wait != KeyWait::Idle {
+                    return Ok(StepOutcome::WaitingForKey);
                 }
+                self.pc = next_pc;
+            }
+            // Set delay timer = Vx
+            Instruction::SetDelay(x) => {
+                bus.set_delay_timer(self.read_reg(x));
+                self.pc = next_pc;
+            }
+            // Set sound timer = Vx
+            Instruction::SetSound(x) => {
+                bus.set_sound_timer(self.read_reg(x));
+                self.pc = next_pc;
+            }
+            // Set I = I + Vx
+            Instruction::AddIndex(x) => {
+                self.i = self.i.wrapping_add(self.read_reg(x) as u16);
+                self.pc = next_pc;
+            }
+            // Set I = location of sprite for digit Vx
+            Instruction::LoadFont(x) => {
+                self.i = memory::FONT_ADDRESS + self.read_reg(x) as u16 * 5;
+                self.pc = next_pc;
+            }
+            // Set I = location of the 10 byte sprite for digit Vx
+            Instruction::LoadBigFont(x) => {
+                self.i = memory::BIG_FONT_ADDRESS + (self.read_reg(x) & 0xf) as u16 * 10;
+                self.pc = next_pc;
+            }
+            // Store BCD representation of Vx in memory locations I, I+1, I+2
+            Instruction::StoreBcd(x) => {
+                let val = self.read_reg(x);
+                bus.memory_write_byte(self.i, val / 100);
+                bus.memory_write_byte(self.i.wrapping_add(1), (val % 100) / 10);
+                bus.memory_write_byte(self.i.wrapping_add(2), val % 10);
+                self.pc = next_pc;
+            }
+            // Set the audio pattern pitch = Vx
+            Instruction::SetPitch(x) => {
+                bus.set_pitch(self.read_reg(x));
+                self.pc = next_pc;
+            }
+            // Store registers V0 through Vx in memory starting at location I
+            Instruction::Store(x) => {
+                for index in 0..=x {
+                    bus.memory_write_byte(self.i.wrapping_add(index as u16), self.read_reg(index));
+                }
+                self.increment_index_after_load_store(x);
+                self.pc = next_pc;
+            }
+            // Read registers V0 through Vx from memory starting at location I
+            Instruction::Load(x) => {
+                for index in 0..=x {
+                    self.write_reg(index, bus.memory_read_byte(self.i.wrapping_add(index as u16)))
+                }
+                self.increment_index_after_load_store(x);
+                self.pc = next_pc;
+            }
+            // Store registers V0 through Vx in the RPL user flags
+            Instruction::SaveFlags(x) => {
+                self.rpl[..=x as usize].copy_from_slice(&self.v[..=x as usize]);
+                self.pc = next_pc;
+            }
+            // Read registers V0 through Vx from the RPL user flags
+            Instruction::LoadFlags(x) => {
+                self.v[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+                self.pc = next_pc;
             }
-
-            _ => panic!("Unhandled or unknown instruction ({:#x})", instruction)
         }
 
-
+        Ok(StepOutcome::Executed)
     }
 
     pub fn write_reg(&mut self, index: u8, value: u8) {
@@ -293,17 +451,182 @@ impl Cpu {
         self.write_reg(15, value);
     }
 
+    /// Get the program counter
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Set the program counter, e.g. from a debugger
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Get the I register
+    pub fn get_i(&self) -> u16 {
+        self.i
+    }
+
+    /// Set the I register
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    /// Get the V0 through VF registers
+    pub fn get_registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    /// Get the return addresses on the stack, oldest first
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack[1..=self.sp as usize]
+    }
+
+    /// Get the stack pointer
+    pub fn get_sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// Get the quirks the CPU is running under
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Whether the program ran the SUPER-CHIP exit instruction
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Whether the CPU is halted on FX0A waiting for a key
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait != KeyWait::Idle
+    }
+
     pub fn read_reg(&self, index: u8) -> u8 {
         self.v[index as usize]
     }
 
+    /// The quirks are part of the machine configuration and are not saved
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.v);
+        writer.write_u16(self.i);
+        writer.write_u16(self.pc);
+        for address in self.stack.iter() {
+            writer.write_u16(*address);
+        }
+        writer.write_u8(self.sp);
+        writer.write_bytes(&self.rpl);
+        match self.key_wait {
+            KeyWait::Idle => writer.write_bytes(&[0, 0]),
+            KeyWait::Press => writer.write_bytes(&[1, 0]),
+            KeyWait::Release(key) => writer.write_bytes(&[2, key]),
+        }
+        writer.write_bool(self.exited);
+        self.rng.save_state(writer);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.v)?;
+        self.i = reader.read_u16()?;
+        self.pc = reader.read_u16()?;
+        for address in self.stack.iter_mut() {
+            *address = reader.read_u16()?;
+        }
+        self.sp = reader.read_u8()?;
+        if self.sp as usize >= self.stack.len() {
+            return Err(StateError::InvalidValue("stack pointer"));
+        }
+        reader.read_into(&mut self.rpl)?;
+        self.key_wait = match (reader.read_u8()?, reader.read_u8()?) {
+            (0, _) => KeyWait::Idle,
+            (1, _) => KeyWait::Press,
+            (2, key) => KeyWait::Release(key),
+            _ => return Err(StateError::InvalidValue("key wait")),
+        };
+        self.exited = reader.read_bool()?;
+        self.rng.load_state(reader)
+    }
+
+    fn reset_flag_reg(&mut self) {
+        if self.quirks.vf_reset {
+            self.write_flag_reg(0);
+        }
+    }
+
+    fn read_shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.read_reg(y)
+        }
+        else {
+            self.read_reg(x)
+        }
+    }
+
+    fn increment_index_after_load_store(&mut self, x: u8) {
+        match self.quirks.load_store {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::ByX => self.i = self.i.wrapping_add(x as u16),
+            IndexIncrement::ByXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+        }
+    }
+
+    /// Skip the next instruction if `condition` holds, otherwise move on to it at `next_pc`
+    fn skip_if(&mut self, condition: bool, next_pc: u16, bus: &Bus) -> Result<(), CpuError> {
+        if condition {
+            self.skip_next_instruction(bus)?;
+        }
+        else {
+            self.pc = next_pc;
+        }
+        Ok(())
+    }
+
+    /// Skip over the next instruction, which is 4 bytes long for the XO-CHIP F000 NNNN
+    fn skip_next_instruction(&mut self, bus: &Bus) -> Result<(), CpuError> {
+        let next = self.pc.wrapping_add(2);
+        let long_load = bus.memory_peek_byte(next) == 0xF0 && bus.memory_peek_byte(next.wrapping_add(1)) == 0x00;
+        let length = if long_load && self.quirks.instruction_set >= InstructionSet::XoChip { 4 } else { 2 };
+        self.pc = self.pc_after(2 + length)?;
+        Ok(())
+    }
+
+    /// The address `size` bytes past the program counter, which must not run past the end of the address space
+    fn pc_after(&self, size: u16) -> Result<u16, CpuError> {
+        self.pc.checked_add(size).ok_or(CpuError::PcOutOfBounds { pc: self.pc })
+    }
+
+    /// Registers x through y, counting down if x is greater than y
+    fn register_range(x: u8, y: u8) -> impl Iterator<Item = u8> {
+        let count = x.abs_diff(y);
+        (0..=count).map(move |offset| if x <= y { x + offset } else { x - offset })
+    }
+
     fn draw_sprite(&mut self, bus: &mut Bus, x: u8, y: u8, height: u8) {
+        // The starting position always wraps, only the sprite itself is clipped
+        let x = x % bus.get_display_width() as u8;
+        let y = y % bus.get_display_height() as u8;
+        let clip = self.quirks.clip_sprites;
+        let (height, bytes_per_row) = if height == 0 && self.quirks.instruction_set >= InstructionSet::SuperChip {
+            (16, 2)
+        } else {
+            (height, 1)
+        };
         let mut should_set_vf = false;
-        for sprite_y in 0..height {
-            let b = bus.memory_read_byte(self.i + sprite_y as u16);
-            if bus.draw_byte(b, x, y + sprite_y) {
-                should_set_vf = true;
+        // With both XO-CHIP planes selected, the sprite for the second plane follows the first
+        let mut address = self.i;
+        let planes = bus.get_planes();
+        for plane in [1, 2].iter().filter(|plane| planes & **plane != 0) {
+            for sprite_y in 0..height {
+                if clip && (y + sprite_y) as usize >= bus.get_display_height() {
+                    break;
+                }
+                for column in 0..bytes_per_row {
+                    let b = bus.memory_read_byte(address.wrapping_add((sprite_y * bytes_per_row + column) as u16));
+                    if bus.draw_byte(b, x + column * 8, y + sprite_y, clip, *plane) {
+                        should_set_vf = true;
+                    }
+                }
             }
+            address = address.wrapping_add((height * bytes_per_row) as u16);
         }
         if should_set_vf {
             self.write_flag_reg(1);
@@ -317,6 +640,11 @@ impl Cpu {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::display;
+
+    pub fn pixel_at(bus: &Bus, x: usize, y: usize) -> u8 {
+        bus.get_display_buffer()[y * bus.get_display_width() + x]
+    }
 
     pub fn put_first_instruction(bus: &mut Bus, instruction: u16) {
         bus.memory_write_byte(0x200, ((instruction & 0xff00) >> 8) as u8);
@@ -325,121 +653,122 @@ mod test {
 
     #[test]
     pub fn test_2nnn() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         put_first_instruction(&mut bus, 0x2345);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(0x345, cpu.pc);
         assert_eq!(1, cpu.sp);
         assert_eq!(previous_pc + 2, cpu.stack[1]);
+        assert_eq!(cpu.get_stack(), &[previous_pc + 2]);
     }
 
     #[test]
     pub fn test_3xkk_not_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x44;
         put_first_instruction(&mut bus, 0x3145);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
     }
 
     #[test]
     pub fn test_3xkk_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x45;
         put_first_instruction(&mut bus, 0x3145);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 4, cpu.pc);
     }
 
     #[test]
     pub fn test_4xkk_not_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x54;
         put_first_instruction(&mut bus, 0x4155);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 4, cpu.pc);
     }
 
     #[test]
     pub fn test_4xkk_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x55;
         put_first_instruction(&mut bus, 0x4155);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc); 
     }
 
     #[test]
     pub fn test_5xy0_not_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x45;
         cpu.v[2] = 0x46;
         put_first_instruction(&mut bus, 0x5120);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
     }
 
     #[test]
     pub fn test_5xy0_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x46;
         cpu.v[2] = 0x46;
         put_first_instruction(&mut bus, 0x5120);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 4, cpu.pc);   
     }
 
     #[test]
-    #[should_panic]
     pub fn test_5xy0_last_0() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x46;
         cpu.v[2] = 0x46;
         put_first_instruction(&mut bus, 0x5121);
 
-        cpu.run_instruction(&mut bus);
+        let result = cpu.run_instruction(&mut bus);
 
-        assert_eq!(previous_pc + 4, cpu.pc); 
+        assert_eq!(result, Err(CpuError::InvalidOpcode { pc: previous_pc, opcode: 0x5121 }));
+        assert_eq!(previous_pc, cpu.pc);
     }
 
     #[test]
     pub fn test_6xkk() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         put_first_instruction(&mut bus, 0x6145);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x45);
@@ -447,27 +776,40 @@ mod test {
 
     #[test]
     pub fn test_7xkk() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x30;
         put_first_instruction(&mut bus, 0x7145);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x30 + 0x45);
     }
 
+    #[test]
+    pub fn test_cxkk() {
+        let mut cpu = Cpu::with_rng(Quirks::default(), Xorshift::new(42));
+        let mut expected = Xorshift::new(42);
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0xC30F);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.pc, PROGRAM_START + 2);
+        assert_eq!(cpu.v[3], expected.next_byte() & 0x0F);
+    }
+
     #[test]
     pub fn test_8xy0() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[2] = 0x30;
         put_first_instruction(&mut bus, 0x8120);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x30);
@@ -475,14 +817,14 @@ mod test {
 
     #[test]
     pub fn test_8xy1() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x05;
         cpu.v[2] = 0x30;
         put_first_instruction(&mut bus, 0x8121);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x30 | 0x05);
@@ -490,14 +832,14 @@ mod test {
 
     #[test]
     pub fn test_8xy2() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x05;
         cpu.v[2] = 0x30;
         put_first_instruction(&mut bus, 0x8122);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x30 & 0x05);
@@ -505,14 +847,14 @@ mod test {
 
     #[test]
     pub fn test_8xy3() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x05;
         cpu.v[2] = 0x30;
         put_first_instruction(&mut bus, 0x8123);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x30 ^ 0x05);
@@ -520,14 +862,14 @@ mod test {
 
     #[test]
     pub fn test_8xy4_no_carry() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x06;
         cpu.v[2] = 0x30;
         put_first_instruction(&mut bus, 0x8124);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x30 + 0x06);
@@ -536,14 +878,14 @@ mod test {
 
     #[test]
     pub fn test_8xy4_set_carry() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0xFE;
         cpu.v[2] = 0x03;
         put_first_instruction(&mut bus, 0x8124);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x1);
@@ -552,14 +894,14 @@ mod test {
 
     #[test]
     pub fn test_8xy5_no_borrow() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x31;
         cpu.v[2] = 0x30;
         put_first_instruction(&mut bus, 0x8125);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x31 - 0x30);
@@ -568,14 +910,14 @@ mod test {
 
     #[test]
     pub fn test_8xy5_borrow() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x31;
         cpu.v[2] = 0x32;
         put_first_instruction(&mut bus, 0x8125);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0xFF);
@@ -584,13 +926,13 @@ mod test {
 
     #[test]
     pub fn test_8xy6_lsb_0() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks { shift_uses_vy: false, ..Quirks::default() });
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x40;
         put_first_instruction(&mut bus, 0x8126);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x40 >> 1);
@@ -599,13 +941,13 @@ mod test {
 
     #[test]
     pub fn test_8xy6_lsb_1() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks { shift_uses_vy: false, ..Quirks::default() });
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x41;
         put_first_instruction(&mut bus, 0x8126);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x41 >> 1);
@@ -614,14 +956,14 @@ mod test {
 
     #[test]
     pub fn test_8xy7_no_borrow() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x40;
         cpu.v[2] = 0x41;
         put_first_instruction(&mut bus, 0x8127);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x1);
@@ -630,31 +972,44 @@ mod test {
 
     #[test]
     pub fn test_8xy7_borrow() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x41;
         cpu.v[2] = 0x40;
         put_first_instruction(&mut bus, 0x8127);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0xFF);
         assert_eq!(cpu.v[15], 0);
     }
 
+    #[test]
+    pub fn test_8xy7_equal_into_vf() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        cpu.v[15] = 0x40;
+        cpu.v[2] = 0x40;
+        put_first_instruction(&mut bus, 0x8F27);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        // The flag is written after the result, and equal values don't borrow
+        assert_eq!(cpu.v[15], 1);
+    }
 
     #[test]
     #[allow(non_snake_case)]
     pub fn test_8xyE_msb_0() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks { shift_uses_vy: false, ..Quirks::default() });
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x41;
         put_first_instruction(&mut bus, 0x812E);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x41 << 1);
@@ -664,13 +1019,13 @@ mod test {
     #[test]
     #[allow(non_snake_case)]
     pub fn test_8xyE_msb_1() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks { shift_uses_vy: false, ..Quirks::default() });
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x81;
         put_first_instruction(&mut bus, 0x812E);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.v[1], 0x81 << 1);
@@ -679,56 +1034,56 @@ mod test {
 
     #[test]
     pub fn test_9xy0_not_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x81;
         cpu.v[2] = 0x82;
         put_first_instruction(&mut bus, 0x9120);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 4, cpu.pc);
     }
 
     #[test]
     pub fn test_9xy0_equal() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x81;
         cpu.v[2] = 0x81;
         put_first_instruction(&mut bus, 0x9120);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
     }
 
     #[test]
-    #[should_panic]
     pub fn test_9xy0_last_0() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         cpu.v[1] = 0x81;
         cpu.v[2] = 0x81;
         put_first_instruction(&mut bus, 0x9121);
 
-        cpu.run_instruction(&mut bus);
+        let result = cpu.run_instruction(&mut bus);
 
-        assert_eq!(previous_pc + 2, cpu.pc);
+        assert_eq!(result, Err(CpuError::InvalidOpcode { pc: previous_pc, opcode: 0x9121 }));
+        assert_eq!(previous_pc, cpu.pc);
     }
 
     #[test]
     #[allow(non_snake_case)]
     pub fn test_Annn() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         let previous_pc = cpu.pc;
         put_first_instruction(&mut bus, 0xA123);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(previous_pc + 2, cpu.pc);
         assert_eq!(cpu.i, 0x123);
@@ -737,16 +1092,646 @@ mod test {
     #[test]
     #[allow(non_snake_case)]
     pub fn test_Bnnn() {
-        let mut cpu = Cpu::new();
+        let mut cpu = Cpu::new(Quirks::default());
         let mut bus = Bus::new();
         cpu.v[0] = 0x45;
         put_first_instruction(&mut bus, 0xB123);
 
-        cpu.run_instruction(&mut bus);
+        cpu.run_instruction(&mut bus).unwrap();
 
         assert_eq!(cpu.pc, 0x123 + 0x45);
     }
 
+    #[test]
+    pub fn test_quirk_shift_uses_vy() {
+        let mut cpu = Cpu::new(Quirks { shift_uses_vy: true, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.v[1] = 0x40;
+        cpu.v[2] = 0x81;
+        put_first_instruction(&mut bus, 0x8126);
+
+        cpu.run_instruction(&mut bus).unwrap();
 
+        assert_eq!(cpu.v[1], 0x81 >> 1);
+        assert_eq!(cpu.v[15], 1);
+    }
+
+    #[test]
+    pub fn test_quirk_shift_in_place() {
+        let mut cpu = Cpu::new(Quirks { shift_uses_vy: false, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.v[1] = 0x40;
+        cpu.v[2] = 0x81;
+        put_first_instruction(&mut bus, 0x812E);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.v[1], 0x40 << 1);
+        assert_eq!(cpu.v[15], 0);
+    }
+
+    pub fn run_load_store(load_store: IndexIncrement, instruction: u16) -> Cpu {
+        let mut cpu = Cpu::new(Quirks { load_store, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.i = 0x300;
+        put_first_instruction(&mut bus, instruction);
 
-}
\ No newline at end of file
+        cpu.run_instruction(&mut bus).unwrap();
+        cpu
+    }
+
+    #[test]
+    pub fn test_quirk_load_store_unchanged() {
+        assert_eq!(run_load_store(IndexIncrement::Unchanged, 0xF255).i, 0x300);
+        assert_eq!(run_load_store(IndexIncrement::Unchanged, 0xF265).i, 0x300);
+    }
+
+    #[test]
+    pub fn test_quirk_load_store_by_x() {
+        assert_eq!(run_load_store(IndexIncrement::ByX, 0xF255).i, 0x302);
+        assert_eq!(run_load_store(IndexIncrement::ByX, 0xF265).i, 0x302);
+    }
+
+    #[test]
+    pub fn test_quirk_load_store_by_x_plus_one() {
+        assert_eq!(run_load_store(IndexIncrement::ByXPlusOne, 0xF255).i, 0x303);
+        assert_eq!(run_load_store(IndexIncrement::ByXPlusOne, 0xF265).i, 0x303);
+    }
+
+    #[test]
+    pub fn test_quirk_jump_uses_vx() {
+        let mut cpu = Cpu::new(Quirks { jump_uses_vx: true, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.v[0] = 0x45;
+        cpu.v[1] = 0x10;
+        put_first_instruction(&mut bus, 0xB123);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.pc, 0x123 + 0x10);
+    }
+
+    #[test]
+    pub fn test_quirk_jump_uses_v0() {
+        let mut cpu = Cpu::new(Quirks { jump_uses_vx: false, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.v[0] = 0x45;
+        cpu.v[1] = 0x10;
+        put_first_instruction(&mut bus, 0xB123);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.pc, 0x123 + 0x45);
+    }
+
+    pub fn draw_at_bottom_right(clip_sprites: bool) -> Bus {
+        let mut cpu = Cpu::new(Quirks { clip_sprites, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.i = 0x300;
+        bus.memory_write_byte(0x300, 0xff);
+        bus.memory_write_byte(0x301, 0xff);
+        cpu.v[1] = (display::WIDTH - 4) as u8;
+        cpu.v[2] = (display::HEIGHT - 1) as u8;
+        put_first_instruction(&mut bus, 0xD122);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        bus
+    }
+
+    #[test]
+    pub fn test_quirk_clip_sprites() {
+        let bus = draw_at_bottom_right(true);
+        let screen = bus.get_display_buffer();
+
+        assert_eq!(screen.iter().filter(|pixel| **pixel == 1).count(), 4);
+        assert_eq!(pixel_at(&bus, 0, 0), 0);
+    }
+
+    #[test]
+    pub fn test_quirk_wrap_sprites() {
+        let bus = draw_at_bottom_right(false);
+        let screen = bus.get_display_buffer();
+
+        assert_eq!(screen.iter().filter(|pixel| **pixel == 1).count(), 16);
+        assert_eq!(pixel_at(&bus, 0, 0), 1);
+    }
+
+    #[test]
+    pub fn test_quirk_wrap_start_position() {
+        let mut cpu = Cpu::new(Quirks { clip_sprites: true, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.i = 0x300;
+        bus.memory_write_byte(0x300, 0x80);
+        cpu.v[1] = (display::WIDTH + 1) as u8;
+        cpu.v[2] = (display::HEIGHT + 2) as u8;
+        put_first_instruction(&mut bus, 0xD121);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(pixel_at(&bus, 1, 2), 1);
+    }
+
+    #[test]
+    pub fn test_quirk_display_wait() {
+        let mut cpu = Cpu::new(Quirks { display_wait: true, ..Quirks::default() });
+        let mut bus = Bus::new();
+        cpu.i = 0x300;
+        bus.memory_write_byte(0x300, 0x80);
+        // Draw, then draw again
+        put_first_instruction(&mut bus, 0xD011);
+        bus.memory_write_byte(0x202, 0xD0);
+        bus.memory_write_byte(0x203, 0x11);
+
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::WaitingForVblank));
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::WaitingForVblank));
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(pixel_at(&bus, 0, 0), 1);
+
+        bus.signal_vblank();
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(pixel_at(&bus, 0, 0), 0);
+    }
+
+    #[test]
+    pub fn test_quirk_no_display_wait() {
+        let mut cpu = Cpu::new(Quirks { display_wait: false, ..Quirks::default() });
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0xD011);
+        bus.memory_write_byte(0x202, 0xD0);
+        bus.memory_write_byte(0x203, 0x11);
+
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    pub fn test_quirk_vf_reset() {
+        for instruction in [0x8121, 0x8122, 0x8123].iter() {
+            let mut cpu = Cpu::new(Quirks { vf_reset: true, ..Quirks::default() });
+            let mut bus = Bus::new();
+            cpu.v[15] = 1;
+            put_first_instruction(&mut bus, *instruction);
+
+            cpu.run_instruction(&mut bus).unwrap();
+
+            assert_eq!(cpu.v[15], 0);
+        }
+    }
+
+    #[test]
+    pub fn test_quirk_vf_preserved() {
+        for instruction in [0x8121, 0x8122, 0x8123].iter() {
+            let mut cpu = Cpu::new(Quirks { vf_reset: false, ..Quirks::default() });
+            let mut bus = Bus::new();
+            cpu.v[15] = 1;
+            put_first_instruction(&mut bus, *instruction);
+
+            cpu.run_instruction(&mut bus).unwrap();
+
+            assert_eq!(cpu.v[15], 1);
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_opcodes() {
+        for instruction in [0x8128, 0xE1FF, 0xF1FF].iter() {
+            let mut cpu = Cpu::new(Quirks::default());
+            let mut bus = Bus::new();
+            put_first_instruction(&mut bus, *instruction);
+
+            let result = cpu.run_instruction(&mut bus);
+
+            assert_eq!(result, Err(CpuError::InvalidOpcode { pc: PROGRAM_START, opcode: *instruction }));
+        }
+    }
+
+    #[test]
+    pub fn test_stack_overflow() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        // Call the same subroutine recursively
+        put_first_instruction(&mut bus, 0x2200);
+
+        for _ in 0..15 {
+            cpu.run_instruction(&mut bus).unwrap();
+        }
+        let result = cpu.run_instruction(&mut bus);
+
+        assert_eq!(result, Err(CpuError::StackOverflow { pc: PROGRAM_START }));
+    }
+
+    #[test]
+    pub fn test_stack_underflow() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0x00EE);
+
+        let result = cpu.run_instruction(&mut bus);
+
+        assert_eq!(result, Err(CpuError::StackUnderflow { pc: PROGRAM_START }));
+        assert_eq!(cpu.sp, 0);
+    }
+
+    #[test]
+    pub fn test_pc_out_of_bounds() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        cpu.v[0] = 0xff;
+        put_first_instruction(&mut bus, 0xBFFF);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        let result = cpu.run_instruction(&mut bus);
+
+        assert_eq!(result, Err(CpuError::PcOutOfBounds { pc: 0xfff + 0xff }));
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Ex9E_with_multiple_keys_down() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        let previous_pc = cpu.pc;
+        cpu.v[1] = 0x8;
+        bus.key_down(0x2);
+        bus.key_down(0x8);
+        put_first_instruction(&mut bus, 0xE19E);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(previous_pc + 4, cpu.pc);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_ExA1_after_key_up() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        let previous_pc = cpu.pc;
+        cpu.v[1] = 0x8;
+        bus.key_down(0x2);
+        bus.key_down(0x8);
+        bus.key_up(0x8);
+        put_first_instruction(&mut bus, 0xE1A1);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(previous_pc + 4, cpu.pc);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Fx0A_waits_for_press_and_release() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        let previous_pc = cpu.pc;
+        put_first_instruction(&mut bus, 0xF30A);
+
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::WaitingForKey));
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(previous_pc, cpu.pc);
+
+        bus.key_down(0x7);
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(previous_pc, cpu.pc);
+
+        bus.key_up(0x7);
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::Executed));
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!(previous_pc + 2, cpu.pc);
+        assert_eq!(cpu.v[3], 0x7);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Fx0A_ignores_other_key_release() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0xF30A);
+
+        bus.key_down(0x2);
+        cpu.run_instruction(&mut bus).unwrap();
+        bus.key_down(0x9);
+        bus.key_up(0x9);
+
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::WaitingForKey));
+
+        bus.key_up(0x2);
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.v[3], 0x2);
+    }
+
+    pub fn new_superchip_cpu() -> Cpu {
+        Cpu::new(Quirks { instruction_set: InstructionSet::SuperChip, ..Quirks::default() })
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_00FF_00FE_switch_resolution() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0x00FF);
+        bus.memory_write_byte(0x202, 0x00);
+        bus.memory_write_byte(0x203, 0xFE);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        assert_eq!(bus.get_display_width(), display::HIRES_WIDTH);
+        assert_eq!(bus.get_display_height(), display::HIRES_HEIGHT);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        assert_eq!(bus.get_display_width(), display::WIDTH);
+        assert_eq!(bus.get_display_height(), display::HEIGHT);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_00CN() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        bus.draw_byte(0x80, 0, 0, true, 1);
+        put_first_instruction(&mut bus, 0x00C3);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(pixel_at(&bus, 0, 0), 0);
+        assert_eq!(pixel_at(&bus, 0, 3), 1);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_00FB_00FC() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        bus.draw_byte(0x80, 8, 0, true, 1);
+        put_first_instruction(&mut bus, 0x00FB);
+        bus.memory_write_byte(0x202, 0x00);
+        bus.memory_write_byte(0x203, 0xFC);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        assert_eq!(pixel_at(&bus, 12, 0), 1);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        assert_eq!(pixel_at(&bus, 8, 0), 1);
+        assert_eq!(pixel_at(&bus, 12, 0), 0);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_00FD() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0x00FD);
+
+        assert_eq!(cpu.run_instruction(&mut bus), Ok(StepOutcome::Exited));
+        assert!(cpu.has_exited());
+        assert_eq!(cpu.pc, PROGRAM_START);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Dxy0_draws_16x16_sprite() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        bus.set_hires(true);
+        cpu.i = 0x300;
+        for offset in 0..32 {
+            bus.memory_write_byte(0x300 + offset, 0xff);
+        }
+        cpu.v[1] = 10;
+        cpu.v[2] = 20;
+        put_first_instruction(&mut bus, 0xD120);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(bus.get_display_buffer().iter().filter(|pixel| **pixel == 1).count(), 16 * 16);
+        assert_eq!(pixel_at(&bus, 25, 35), 1);
+        assert_eq!(pixel_at(&bus, 26, 35), 0);
+        assert_eq!(cpu.v[15], 0);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Dxy0_without_superchip_draws_nothing() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        cpu.i = 0x300;
+        bus.memory_write_byte(0x300, 0xff);
+        put_first_instruction(&mut bus, 0xD120);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert!(bus.get_display_buffer().iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Fx30() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        cpu.v[1] = 0x7;
+        put_first_instruction(&mut bus, 0xF130);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.i, memory::BIG_FONT_ADDRESS + 70);
+        assert_eq!(bus.memory_read_byte(cpu.i + 4), 0x06);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Fx30_without_superchip() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0xF130);
+
+        let result = cpu.run_instruction(&mut bus);
+
+        assert_eq!(result, Err(CpuError::InvalidOpcode { pc: PROGRAM_START, opcode: 0xF130 }));
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Fx75_Fx85() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        cpu.v[0] = 0x12;
+        cpu.v[1] = 0x34;
+        cpu.v[2] = 0x56;
+        put_first_instruction(&mut bus, 0xF175);
+        bus.memory_write_byte(0x202, 0xF2);
+        bus.memory_write_byte(0x203, 0x85);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        cpu.v[0] = 0;
+        cpu.v[1] = 0;
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.v[0], 0x12);
+        assert_eq!(cpu.v[1], 0x34);
+        assert_eq!(cpu.v[2], 0);
+    }
+
+    pub fn new_xochip_cpu() -> Cpu {
+        Cpu::new(Quirks { instruction_set: InstructionSet::XoChip, ..Quirks::default() })
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_F000_nnnn() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        put_first_instruction(&mut bus, 0xF000);
+        bus.memory_write_byte(0x202, 0xAB);
+        bus.memory_write_byte(0x203, 0xCD);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.i, 0xABCD);
+        assert_eq!(cpu.pc, PROGRAM_START + 4);
+    }
+
+    #[test]
+    pub fn test_skip_over_long_load() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        put_first_instruction(&mut bus, 0x3000);
+        bus.memory_write_byte(0x202, 0xF0);
+        bus.memory_write_byte(0x203, 0x00);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.pc, PROGRAM_START + 6);
+    }
+
+    #[test]
+    pub fn test_pc_overflow_at_end_of_memory() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        bus.memory_write_byte(0xFFFC, 0xF0);
+        bus.memory_write_byte(0xFFFD, 0x00);
+        bus.memory_write_byte(0xFFFE, 0x12);
+        bus.memory_write_byte(0xFFFF, 0x34);
+        cpu.pc = 0xFFFC;
+        cpu.i = 0x300;
+        assert_eq!(cpu.run_instruction(&mut bus), Err(CpuError::PcOutOfBounds { pc: 0xFFFC }));
+        // The failed long load leaves I alone
+        assert_eq!(cpu.i, 0x300);
+
+        bus.memory_write_byte(0xFFFC, 0x30);
+        bus.memory_write_byte(0xFFFD, 0x00);
+        cpu.pc = 0xFFFC;
+        assert_eq!(cpu.run_instruction(&mut bus), Err(CpuError::PcOutOfBounds { pc: 0xFFFC }));
+    }
+
+    #[test]
+    pub fn test_pc_overflow_at_last_instruction() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        cpu.v[0] = 1;
+        // A plain instruction, a skip which is taken and a call pushing its return address
+        for opcode in [0x6005u16, 0x3001, 0x2400].iter() {
+            bus.memory_write_byte(0xFFFE, (opcode >> 8) as u8);
+            bus.memory_write_byte(0xFFFF, (opcode & 0xff) as u8);
+            cpu.pc = 0xFFFE;
+            assert_eq!(cpu.run_instruction(&mut bus), Err(CpuError::PcOutOfBounds { pc: 0xFFFE }));
+        }
+        assert_eq!(cpu.v[0], 1);
+        assert_eq!(cpu.sp, 0);
+    }
+
+    #[test]
+    pub fn test_skip_over_long_load_without_xochip() {
+        let mut cpu = new_superchip_cpu();
+        let mut bus = Bus::new();
+        put_first_instruction(&mut bus, 0x3000);
+        bus.memory_write_byte(0x202, 0xF0);
+        bus.memory_write_byte(0x203, 0x00);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(cpu.pc, PROGRAM_START + 4);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_5xy2_5xy3() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        cpu.i = 0x400;
+        cpu.v[2] = 0x22;
+        cpu.v[3] = 0x33;
+        cpu.v[4] = 0x44;
+        put_first_instruction(&mut bus, 0x5242);
+        // Load them back in reverse order
+        bus.memory_write_byte(0x202, 0x5A);
+        bus.memory_write_byte(0x203, 0x83);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        assert_eq!(bus.memory_read_byte(0x400), 0x22);
+        assert_eq!(bus.memory_read_byte(0x402), 0x44);
+        assert_eq!(cpu.i, 0x400);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        assert_eq!(cpu.v[0xA], 0x22);
+        assert_eq!(cpu.v[0x9], 0x33);
+        assert_eq!(cpu.v[0x8], 0x44);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_Fn01_draws_both_planes() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        cpu.i = 0x400;
+        bus.memory_write_byte(0x400, 0xC0);
+        bus.memory_write_byte(0x401, 0x80);
+        put_first_instruction(&mut bus, 0xF301);
+        bus.memory_write_byte(0x202, 0xD0);
+        bus.memory_write_byte(0x203, 0x01);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(pixel_at(&bus, 0, 0), 3);
+        assert_eq!(pixel_at(&bus, 1, 0), 1);
+        assert_eq!(cpu.v[15], 0);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_00DN() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        bus.draw_byte(0x80, 0, 4, true, 1);
+        put_first_instruction(&mut bus, 0x00D3);
+
+        cpu.run_instruction(&mut bus).unwrap();
+
+        assert_eq!(pixel_at(&bus, 0, 1), 1);
+        assert_eq!(pixel_at(&bus, 0, 4), 0);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    pub fn test_F002_Fx3A() {
+        let mut cpu = new_xochip_cpu();
+        let mut bus = Bus::with_memory_size(memory::XO_CHIP_SIZE);
+        cpu.i = 0x400;
+        cpu.v[5] = 100;
+        for offset in 0..16 {
+            bus.memory_write_byte(0x400 + offset, offset as u8);
+        }
+        put_first_instruction(&mut bus, 0xF002);
+        bus.memory_write_byte(0x202, 0xF5);
+        bus.memory_write_byte(0x203, 0x3A);
+
+        cpu.run_instruction(&mut bus).unwrap();
+        cpu.run_instruction(&mut bus).unwrap();
+
+        let pattern = bus.get_audio_pattern();
+        assert_eq!(pattern.pattern, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+        assert_eq!(pattern.pitch, 100);
+    }
+}
@@ -0,0 +1,523 @@
+//! A debugger wrapping `Chip8` with breakpoints and stepping, driven by text
+//! commands so it can be used from a terminal prompt next to the window
+
+use crate::chip8::Chip8;
+use crate::cpu::{CpuError, StepOutcome};
+use crate::instruction::{decode_for, Instruction, Syntax};
+use crate::watchpoint::{WatchHit, WatchKind, Watchpoint};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Bytes printed by `mem` when no length is given
+const DEFAULT_DUMP_LENGTH: usize = 32;
+
+/// Help printed for the `help` command
+const HELP: &str = "\
+break ADDR      (b)  set a breakpoint at an address or label
+delete ADDR     (d)  remove a breakpoint
+breakpoints          list breakpoints
+continue        (c)  resume running
+pause           (p)  stop running
+step [N]        (s)  run N instructions, 1 by default
+next            (n)  step over a subroutine call
+finish          (f)  run until the current subroutine returns
+until ADDR      (u)  run to an address or label
+regs            (r)  show registers, I, timers and the stack
+mem ADDR [LEN]  (x)  dump memory
+watch ADDR [LEN]     pause when memory is written
+cwatch ADDR [LEN]    pause when memory is changed
+rwatch ADDR [LEN]    pause when memory is read
+unwatch ADDR         remove the watchpoints covering an address
+watchpoints          list watchpoints
+";
+
+/// Why the debugger paused a running program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// A breakpoint at this address was reached
+    Breakpoint(u16),
+    /// A step over, step out or run to cursor finished at this address
+    Reached(u16),
+    /// The instruction at `pc` triggered a watchpoint
+    Watchpoint { pc: u16, hit: WatchHit },
+}
+
+/// Where `next`, `finish` and `until` stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Target {
+    /// Stop at this address, or anywhere when `None`
+    pc: Option<u16>,
+    /// Only stop once the call stack is at most this deep
+    max_sp: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    RunningTo(Target),
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    /// Names of addresses, e.g. from assembled source
+    labels: BTreeMap<String, u16>,
+    state: RunState,
+    /// Don't stop at a breakpoint on the instruction execution resumed from
+    resuming: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+
+    /// Create a debugger which lets the program run until a breakpoint is hit
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            labels: BTreeMap::new(),
+            state: RunState::Running,
+            resuming: false,
+        }
+    }
+
+    /// Let breakpoints and run to cursor take label names as well as addresses
+    pub fn set_labels(&mut self, labels: BTreeMap<String, u16>) {
+        self.labels = labels;
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns whether there was a breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    pub fn resume(&mut self) {
+        self.run_to(None, u8::MAX);
+    }
+
+    /// Run one instruction, even if paused, finishing the frame first if it's
+    /// a DXYN waiting for the display. The program stays paused afterwards
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<StepOutcome, CpuError> {
+        self.state = RunState::Paused;
+        chip8.step_instruction()
+    }
+
+    /// Step, running a subroutine call to completion before pausing again
+    pub fn step_over(&mut self, chip8: &mut Chip8) -> Result<(), CpuError> {
+        match (self.current_instruction(chip8), chip8.cpu.get_pc().checked_add(2)) {
+            (Some(Instruction::Call(_)), Some(next)) => {
+                self.run_to(Some(next), chip8.cpu.get_sp());
+                Ok(())
+            }
+            // A call at the end of memory has no return address, stepping reports the error
+            _ => self.step(chip8).map(|_| ()),
+        }
+    }
+
+    /// Run until the current subroutine returns. Returns false outside of a subroutine
+    pub fn step_out(&mut self, chip8: &Chip8) -> bool {
+        match chip8.cpu.get_sp().checked_sub(1) {
+            Some(max_sp) => {
+                self.run_to(None, max_sp);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run until the program counter reaches `address`
+    pub fn run_to_cursor(&mut self, address: u16) {
+        self.run_to(Some(address), u8::MAX);
+    }
+
+    fn run_to(&mut self, pc: Option<u16>, max_sp: u8) {
+        self.state = if pc.is_none() && max_sp == u8::MAX {
+            RunState::Running
+        } else {
+            RunState::RunningTo(Target { pc, max_sp })
+        };
+        self.resuming = true;
+    }
+
+    /// Run a frame like `Chip8::run_frame` unless paused. If a breakpoint or
+    /// target is reached partway through, the debugger pauses there and the
+    /// timers aren't ticked. A CPU error also pauses the debugger
+    pub fn run_frame(&mut self, chip8: &mut Chip8, instructions_per_frame: usize) -> Result<Option<StopReason>, CpuError> {
+        if self.state == RunState::Paused {
+            return Ok(None);
+        }
+
+        // Accesses made outside of instructions, e.g. loading a ROM, aren't reported
+        chip8.bus.take_watch_hit();
+        let mut instructions_run = 0;
+        while chip8.frame_has_room(instructions_run, instructions_per_frame) {
+            instructions_run += 1;
+            if let Some(reason) = self.check_stop(chip8) {
+                self.state = RunState::Paused;
+                return Ok(Some(reason));
+            }
+            self.resuming = false;
+            let pc = chip8.cpu.get_pc();
+            let outcome = chip8.run_instruction();
+            if let Some(hit) = chip8.bus.take_watch_hit() {
+                self.state = RunState::Paused;
+                outcome?;
+                return Ok(Some(StopReason::Watchpoint { pc, hit }));
+            }
+            match outcome {
+                Ok(StepOutcome::Exited) => break,
+                Ok(StepOutcome::WaitingForVblank) => {
+                    // The same DXYN runs again next frame, it isn't a new arrival at a breakpoint
+                    self.resuming = true;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.state = RunState::Paused;
+                    return Err(e);
+                }
+            }
+        }
+        chip8.tick_timers();
+        Ok(None)
+    }
+
+    fn check_stop(&self, chip8: &Chip8) -> Option<StopReason> {
+        let pc = chip8.cpu.get_pc();
+        if let RunState::RunningTo(target) = self.state {
+            if target.pc.map_or(true, |address| address == pc) && chip8.cpu.get_sp() <= target.max_sp {
+                return Some(StopReason::Reached(pc));
+            }
+        }
+        if !self.resuming && self.breakpoints.contains(&pc) {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        None
+    }
+
+    fn current_instruction(&self, chip8: &Chip8) -> Option<Instruction> {
+        self.instruction_at(chip8, chip8.cpu.get_pc())
+    }
+
+    fn instruction_at(&self, chip8: &Chip8, address: u16) -> Option<Instruction> {
+        let opcode = (chip8.bus.memory_peek_byte(address) as u16) << 8 | chip8.bus.memory_peek_byte(address.wrapping_add(1)) as u16;
+        decode_for(opcode, chip8.cpu.get_quirks().instruction_set)
+    }
+
+    /// The address and instruction at the program counter, with its label if it has one
+    pub fn describe_pc(&self, chip8: &Chip8) -> String {
+        self.describe_address(chip8, chip8.cpu.get_pc())
+    }
+
+    /// The address and instruction at `pc`, with its label if it has one
+    pub fn describe_address(&self, chip8: &Chip8, pc: u16) -> String {
+        let label = self.labels.iter()
+            .find(|(_, address)| **address == pc)
+            .map(|(name, _)| format!(" ({})", name))
+            .unwrap_or_default();
+        let instruction = match self.instruction_at(chip8, pc) {
+            Some(instruction) => instruction.format(Syntax::Octo),
+            None => "invalid opcode".to_string(),
+        };
+        format!("{:#05x}{}  {}", pc, label, instruction)
+    }
+
+    /// Registers, I, the timers and the call stack
+    pub fn describe_registers(&self, chip8: &Chip8) -> String {
+        let mut text = String::new();
+        for (index, value) in chip8.cpu.get_registers().iter().enumerate() {
+            let separator = if index % 8 == 7 { '\n' } else { ' ' };
+            let _ = write!(text, "v{:x}={:02x}{}", index, value, separator);
+        }
+        let _ = writeln!(text, "i={:#05x} pc={:#05x} delay={} sound={}",
+            chip8.cpu.get_i(), chip8.cpu.get_pc(), chip8.bus.get_delay_timer(), chip8.bus.get_sound_timer());
+        let stack: Vec<String> = chip8.cpu.get_stack().iter().map(|address| format!("{:#05x}", address)).collect();
+        let _ = writeln!(text, "stack=[{}]", stack.join(" "));
+        text
+    }
+
+    fn describe_memory(&self, chip8: &Chip8, address: u16, length: usize) -> String {
+        let mut text = String::new();
+        let end = (address as usize).saturating_add(length).min(chip8.bus.memory_get_size());
+        for row in (address as usize..end).step_by(16) {
+            let bytes: Vec<String> = (row..end.min(row + 16))
+                .map(|address| format!("{:02x}", chip8.bus.memory_peek_byte(address as u16)))
+                .collect();
+            let _ = writeln!(text, "{:#06x}  {}", row, bytes.join(" "));
+        }
+        text
+    }
+
+    /// Parse an address written in hex with `0x`, in decimal or as a label
+    fn parse_address(&self, text: &str) -> Option<u16> {
+        match text.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok().or_else(|| self.labels.get(text).copied()),
+        }
+    }
+
+    /// Run a prompt command, returning the text to print
+    pub fn execute(&mut self, command: &str, chip8: &mut Chip8) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let (name, arguments) = match words.split_first() {
+            Some((name, arguments)) => (*name, arguments),
+            None => return String::new(),
+        };
+        let address = arguments.first().map(|text| self.parse_address(text).ok_or(*text));
+
+        match (name, address) {
+            ("help" | "h" | "?", _) => HELP.to_string(),
+            ("break" | "b", Some(Ok(address))) => {
+                self.add_breakpoint(address);
+                format!("Breakpoint at {:#05x}\n", address)
+            }
+            ("delete" | "d", Some(Ok(address))) => {
+                if self.remove_breakpoint(address) {
+                    format!("Removed breakpoint at {:#05x}\n", address)
+                } else {
+                    format!("No breakpoint at {:#05x}\n", address)
+                }
+            }
+            ("breakpoints", _) => self.breakpoints().map(|address| format!("{:#05x}\n", address)).collect(),
+            ("continue" | "c", _) => {
+                self.resume();
+                "Running\n".to_string()
+            }
+            ("pause" | "p", _) => {
+                self.pause();
+                format!("Paused at {}\n", self.describe_pc(chip8))
+            }
+            ("step" | "s", _) => {
+                let count = match arguments.first() {
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) => count,
+                        Err(_) => return format!("Invalid step count {}\n", count),
+                    },
+                    None => 1,
+                };
+                chip8.bus.take_watch_hit();
+                for _ in 0..count {
+                    let pc = chip8.cpu.get_pc();
+                    if let Err(e) = self.step(chip8) {
+                        return format!("CHIP-8 crashed: {}\n", e);
+                    }
+                    if let Some(hit) = chip8.bus.take_watch_hit() {
+                        return format!("Watchpoint: {} at {}\n{}\n", hit, self.describe_address(chip8, pc), self.describe_pc(chip8));
+                    }
+                }
+                format!("{}\n", self.describe_pc(chip8))
+            }
+            ("next" | "n", _) => match self.step_over(chip8) {
+                Ok(()) if self.is_paused() => format!("{}\n", self.describe_pc(chip8)),
+                Ok(()) => String::new(),
+                Err(e) => format!("CHIP-8 crashed: {}\n", e),
+            },
+            ("finish" | "f", _) => {
+                if self.step_out(chip8) { String::new() } else { "Not in a subroutine\n".to_string() }
+            }
+            ("until" | "u", Some(Ok(address))) => {
+                self.run_to_cursor(address);
+                String::new()
+            }
+            ("regs" | "r", _) => self.describe_registers(chip8),
+            ("mem" | "x", Some(Ok(address))) => {
+                let length = arguments.get(1).and_then(|length| length.parse().ok()).unwrap_or(DEFAULT_DUMP_LENGTH);
+                self.describe_memory(chip8, address, length)
+            }
+            ("watch" | "cwatch" | "rwatch", Some(Ok(address))) => {
+                let kind = match name {
+                    "watch" => WatchKind::Write,
+                    "cwatch" => WatchKind::Change,
+                    _ => WatchKind::Read,
+                };
+                let length = arguments.get(1).and_then(|length| length.parse::<u16>().ok()).unwrap_or(1).max(1);
+                let watchpoint = Watchpoint::new(address, address.saturating_add(length - 1), kind);
+                chip8.bus.add_watchpoint(watchpoint);
+                format!("Watchpoint on {}\n", watchpoint)
+            }
+            ("unwatch", Some(Ok(address))) => {
+                if chip8.bus.remove_watchpoints(address) {
+                    format!("Removed watchpoints on {:#05x}\n", address)
+                } else {
+                    format!("No watchpoint on {:#05x}\n", address)
+                }
+            }
+            ("watchpoints", _) => chip8.bus.get_watchpoints().iter().map(|watchpoint| format!("{}\n", watchpoint)).collect(),
+            ("break" | "b" | "delete" | "d" | "until" | "u" | "mem" | "x" | "watch" | "cwatch" | "rwatch" | "unwatch", Some(Err(text))) => {
+                format!("Unknown address {}\n", text)
+            }
+            ("break" | "b" | "delete" | "d" | "until" | "u" | "mem" | "x" | "watch" | "cwatch" | "rwatch" | "unwatch", None) => {
+                format!("{} needs an address\n", name)
+            }
+            _ => format!("Unknown command {}, try help\n", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::DRAW_TWICE_PROGRAM;
+    use crate::quirks::Quirks;
+
+    /// call 0x208, V1 = 1, loop forever, V0 += 1, return
+    const ROM: [u8; 12] = [0x22, 0x08, 0x61, 0x01, 0x12, 0x04, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+
+    fn new_chip8() -> Chip8 {
+        Chip8::builder().rom(&ROM).build()
+    }
+
+    #[test]
+    pub fn test_breakpoint_pauses_and_resumes() {
+        let mut chip8 = new_chip8();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x208);
+
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(Some(StopReason::Breakpoint(0x208))));
+        assert!(debugger.is_paused());
+        assert_eq!(chip8.cpu.get_pc(), 0x208);
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(None));
+        assert_eq!(chip8.cpu.get_pc(), 0x208);
+
+        // Resuming doesn't stop again at the same breakpoint
+        debugger.resume();
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(None));
+        assert_eq!(chip8.cpu.read_reg(1), 1);
+    }
+
+    #[test]
+    pub fn test_breakpoint_on_draw_waiting_for_display() {
+        let mut chip8 = Chip8::builder().rom(&DRAW_TWICE_PROGRAM).build();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x202);
+
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(Some(StopReason::Breakpoint(0x202))));
+        debugger.resume();
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(None));
+        assert_eq!(chip8.cpu.get_pc(), 0x202);
+
+        // The draw waiting since the last frame runs without stopping again
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(None));
+        assert_eq!(chip8.cpu.get_pc(), 0x200);
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(Some(StopReason::Breakpoint(0x202))));
+    }
+
+    #[test]
+    pub fn test_step_past_draws_in_a_row() {
+        let mut chip8 = Chip8::builder().rom(&DRAW_TWICE_PROGRAM).build();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+
+        for pc in [0x202, 0x204, 0x200, 0x202, 0x204] {
+            debugger.step(&mut chip8).unwrap();
+            assert_eq!(chip8.cpu.get_pc(), pc);
+        }
+    }
+
+    #[test]
+    pub fn test_step_over_and_out() {
+        let mut chip8 = new_chip8();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+
+        debugger.step_over(&mut chip8).unwrap();
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(Some(StopReason::Reached(0x202))));
+        assert_eq!(chip8.cpu.read_reg(0), 1);
+
+        let mut chip8 = new_chip8();
+        debugger.step(&mut chip8).unwrap();
+        assert_eq!(chip8.cpu.get_pc(), 0x208);
+        assert!(debugger.step_out(&chip8));
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(Some(StopReason::Reached(0x202))));
+        assert!(!debugger.step_out(&chip8));
+    }
+
+    #[test]
+    pub fn test_step_over_call_at_end_of_memory() {
+        let mut chip8 = Chip8::builder().quirks(Quirks::xochip()).build();
+        chip8.bus.memory_write_byte(0xFFFE, 0x22);
+        chip8.bus.memory_write_byte(0xFFFF, 0x00);
+        chip8.cpu.set_pc(0xFFFE);
+        let mut debugger = Debugger::new();
+        debugger.pause();
+
+        assert_eq!(debugger.step_over(&mut chip8), Err(CpuError::PcOutOfBounds { pc: 0xFFFE }));
+    }
+
+    #[test]
+    pub fn test_run_to_cursor() {
+        let mut chip8 = new_chip8();
+        let mut debugger = Debugger::new();
+        debugger.run_to_cursor(0x204);
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(Some(StopReason::Reached(0x204))));
+        assert_eq!(chip8.cpu.read_reg(1), 1);
+    }
+
+    #[test]
+    pub fn test_watchpoint_reports_instruction() {
+        // V0 = 7, I = 0x300, save v0, loop forever
+        let mut chip8 = Chip8::builder().rom(&[0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]).build();
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.execute("cwatch 0x300", &mut chip8), "Watchpoint on change 0x300\n");
+
+        let hit = WatchHit { kind: WatchKind::Change, address: 0x300, old: 0, new: 7 };
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(Some(StopReason::Watchpoint { pc: 0x204, hit })));
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.describe_address(&chip8, 0x204), "0x204  save v0");
+
+        // Storing the same value again isn't a change
+        debugger.resume();
+        chip8.cpu.write_reg(0, 7);
+        assert_eq!(debugger.run_frame(&mut chip8, 8), Ok(None));
+    }
+
+    #[test]
+    pub fn test_crash_pauses() {
+        let mut chip8 = Chip8::builder().rom(&[0x00, 0xEE]).build();
+        let mut debugger = Debugger::new();
+        assert!(debugger.run_frame(&mut chip8, 8).is_err());
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    pub fn test_commands() {
+        let mut chip8 = new_chip8();
+        let mut debugger = Debugger::new();
+        let mut labels = BTreeMap::new();
+        labels.insert("add".to_string(), 0x208);
+        debugger.set_labels(labels);
+
+        assert_eq!(debugger.execute("b add", &mut chip8), "Breakpoint at 0x208\n");
+        assert_eq!(debugger.execute("breakpoints", &mut chip8), "0x208\n");
+        assert_eq!(debugger.execute("pause", &mut chip8), "Paused at 0x200  :call 0x208\n");
+        assert_eq!(debugger.execute("s", &mut chip8), "0x208 (add)  v0 += 0x01\n");
+        assert_eq!(debugger.execute("step 2", &mut chip8), "0x202  v1 := 0x01\n");
+        assert_eq!(debugger.execute("x 0x200 4", &mut chip8), "0x0200  22 08 61 01\n");
+        assert_eq!(debugger.execute(&format!("x 0xffe {}", usize::MAX), &mut chip8), "0x0ffe  00 00\n");
+        assert!(debugger.execute("regs", &mut chip8).starts_with("v0=01 v1=00"));
+        assert_eq!(debugger.execute("d 0x208", &mut chip8), "Removed breakpoint at 0x208\n");
+        assert_eq!(debugger.execute("until nowhere", &mut chip8), "Unknown address nowhere\n");
+        assert_eq!(debugger.execute("b", &mut chip8), "b needs an address\n");
+        assert_eq!(debugger.execute("frobnicate", &mut chip8), "Unknown command frobnicate, try help\n");
+    }
+}
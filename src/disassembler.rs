@@ -0,0 +1,194 @@
+use crate::cpu::PROGRAM_START;
+use crate::instruction::{decode_for, Instruction, Syntax};
+use crate::memory;
+use crate::quirks::InstructionSet;
+use std::collections::BTreeSet;
+
+/// Most data bytes printed on one line
+const DATA_BYTES_PER_LINE: usize = 4;
+
+/// What a disassembled line holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    /// An instruction reachable from the program start
+    Code(Instruction),
+    /// Bytes never run as an instruction, e.g. sprites
+    Data,
+}
+
+/// One line of a disassembly listing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+}
+
+impl Line {
+
+    /// Print the address, raw bytes and mnemonic or data directive
+    pub fn format(&self, syntax: Syntax) -> String {
+        let raw: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let text = match self.kind {
+            LineKind::Code(Instruction::LoadLongIndex) => {
+                let address = (self.bytes[2] as u16) << 8 | self.bytes[3] as u16;
+                format!("{} {:#06x}", Instruction::LoadLongIndex.format(syntax), address)
+            }
+            LineKind::Code(instruction) => instruction.format(syntax),
+            LineKind::Data => {
+                let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+                match syntax {
+                    Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+                    Syntax::Octo => bytes.join(" "),
+                }
+            }
+        };
+        format!("{:#05x}  {:<11}  {}", self.address, raw.join(" "), text)
+    }
+}
+
+/// Disassemble a ROM loaded at the program start. Only instructions reachable
+/// by following jumps, calls and skips from the program start are treated as
+/// code, everything else is data. Targets of BNNN can't be known and are not followed.
+/// Bytes past the end of the 64 KiB address space are ignored
+pub fn disassemble(rom: &[u8], instruction_set: InstructionSet) -> Vec<Line> {
+    let rom = &rom[..rom.len().min(memory::XO_CHIP_SIZE - PROGRAM_START as usize)];
+    let code = find_code(rom, instruction_set);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let address = PROGRAM_START + offset as u16;
+        if code.contains(&address) {
+            let instruction = decode_at(rom, address, instruction_set).unwrap();
+            let size = instruction.size() as usize;
+            lines.push(Line { address, bytes: rom[offset..offset + size].to_vec(), kind: LineKind::Code(instruction) });
+            offset += size;
+        }
+        else {
+            let mut end = offset + 1;
+            while end < rom.len() && end - offset < DATA_BYTES_PER_LINE && !code.contains(&(PROGRAM_START + end as u16)) {
+                end += 1;
+            }
+            lines.push(Line { address, bytes: rom[offset..end].to_vec(), kind: LineKind::Data });
+            offset = end;
+        }
+    }
+
+    lines
+}
+
+/// Decode the instruction at `address` if it lies completely within the ROM
+fn decode_at(rom: &[u8], address: u16, instruction_set: InstructionSet) -> Option<Instruction> {
+    let offset = address.checked_sub(PROGRAM_START)? as usize;
+    if offset + 1 >= rom.len() {
+        return None;
+    }
+    let instruction = decode_for((rom[offset] as u16) << 8 | rom[offset + 1] as u16, instruction_set)?;
+    if offset + instruction.size() as usize > rom.len() {
+        return None;
+    }
+    Some(instruction)
+}
+
+/// Addresses of every instruction reachable from the program start
+fn find_code(rom: &[u8], instruction_set: InstructionSet) -> BTreeSet<u16> {
+    let mut code = BTreeSet::new();
+    // Bytes covered by an instruction, so overlapping instructions aren't decoded
+    let mut covered = vec![false; rom.len()];
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        let offset = match address.checked_sub(PROGRAM_START) {
+            Some(offset) if (offset as usize) < rom.len() && !covered[offset as usize] => offset as usize,
+            _ => continue,
+        };
+        let instruction = match decode_at(rom, address, instruction_set) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let size = instruction.size() as usize;
+        if covered[offset..offset + size].iter().any(|covered| *covered) {
+            continue;
+        }
+        covered[offset..offset + size].iter_mut().for_each(|covered| *covered = true);
+        code.insert(address);
+
+        // Code running off the end of the address space isn't followed any further
+        let next = address.checked_add(size as u16);
+        match instruction {
+            Instruction::Jump(nnn) | Instruction::Sys(nnn) => pending.push(nnn),
+            Instruction::Call(nnn) => pending.extend(Some(nnn).into_iter().chain(next)),
+            Instruction::Return | Instruction::Exit | Instruction::JumpOffset(_) => {}
+            skip if skip.is_skip() => {
+                if let Some(next) = next {
+                    let skipped_size = decode_at(rom, next, instruction_set).map_or(2, |skipped| skipped.size());
+                    pending.extend(Some(next).into_iter().chain(next.checked_add(skipped_size)));
+                }
+            }
+            _ => pending.extend(next),
+        }
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_code_and_data() {
+        // I = sprite, draw it, loop forever, sprite
+        let rom = [0xA2, 0x06, 0xD0, 0x11, 0x12, 0x04, 0xF0, 0x90, 0x90];
+        let lines = disassemble(&rom, InstructionSet::Chip8);
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].kind, LineKind::Code(Instruction::LoadIndex(0x206)));
+        assert_eq!(lines[2].kind, LineKind::Code(Instruction::Jump(0x204)));
+        assert_eq!(lines[3], Line { address: 0x206, bytes: vec![0xF0, 0x90, 0x90], kind: LineKind::Data });
+
+        assert_eq!(lines[0].format(Syntax::Cowgod), "0x200  A2 06        LD I, 0x206");
+        assert_eq!(lines[1].format(Syntax::Octo), "0x202  D0 11        sprite v0 v1 1");
+        assert_eq!(lines[3].format(Syntax::Cowgod), "0x206  F0 90 90     DB 0xf0, 0x90, 0x90");
+    }
+
+    #[test]
+    pub fn test_follows_calls_and_skips() {
+        // call 0x208, skip if V0 == 0, jump 0x20C (skipped when V0 == 0), return, data, return
+        let rom = [0x22, 0x08, 0x30, 0x00, 0x12, 0x0C, 0x00, 0xEE, 0x00, 0xEE, 0xFF, 0xFF, 0x00, 0xEE];
+        let lines = disassemble(&rom, InstructionSet::Chip8);
+        let code: Vec<u16> = lines.iter()
+            .filter(|line| line.kind != LineKind::Data)
+            .map(|line| line.address)
+            .collect();
+
+        assert_eq!(code, vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20C]);
+    }
+
+    #[test]
+    pub fn test_long_index() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x12, 0x04];
+        let lines = disassemble(&rom, InstructionSet::XoChip);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].format(Syntax::Octo), "0x200  F0 00 12 34  i := long 0x1234");
+
+        let lines = disassemble(&rom, InstructionSet::Chip8);
+        assert!(lines.iter().all(|line| line.kind == LineKind::Data));
+    }
+
+    #[test]
+    pub fn test_code_up_to_end_of_memory() {
+        // Skips all the way to the last instruction, plus bytes past the end of memory
+        let mut rom = vec![0x30; memory::XO_CHIP_SIZE - PROGRAM_START as usize + 4];
+        for byte in rom.iter_mut().skip(1).step_by(2) {
+            *byte = 0x00;
+        }
+        let lines = disassemble(&rom, InstructionSet::XoChip);
+
+        assert_eq!(lines.len(), (memory::XO_CHIP_SIZE - PROGRAM_START as usize) / 2);
+        assert_eq!(lines.last().unwrap().address, 0xFFFE);
+        assert!(lines.iter().all(|line| line.kind == LineKind::Code(Instruction::SkipEqualByte(0, 0))));
+    }
+}
@@ -1,35 +1,84 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Size of the standard low resolution screen
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
 
+/// Size of the SUPER-CHIP high resolution screen
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Each pixel holds one bit per plane, XO-CHIP has two planes giving four colours
+pub const PLANE_COUNT: usize = 2;
+
+/// Default 0xRRGGBB colours for each combination of the two planes
+pub const PALETTE: [u32; 4] = [0x000000, 0xffffff, 0xaaaaaa, 0x555555];
+
 pub struct Display {
-    screen: [u8; WIDTH * HEIGHT],
+    screen: [u8; HIRES_WIDTH * HIRES_HEIGHT],
+    hires: bool,
+    /// Bit mask of the planes affected by drawing, clearing and scrolling
+    planes: u8,
 }
 
 impl Display {
     pub fn new() -> Self {
-        Display { screen: [0; WIDTH * HEIGHT]}
+        Display { screen: [0; HIRES_WIDTH * HIRES_HEIGHT], hires: false, planes: 1 }
+    }
+
+    /// Width of the screen in the current resolution
+    pub fn get_width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { WIDTH }
+    }
+
+    /// Height of the screen in the current resolution
+    pub fn get_height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { HEIGHT }
+    }
+
+    pub fn get_index_from_coords(&self, x: usize, y: usize) -> usize {
+        y * self.get_width() + x
+    }
+
+    /// Switch between the low and high resolution screens, clearing every plane
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        for pixel in self.screen.iter_mut() {
+            *pixel = 0;
+        }
+    }
+
+    /// Select the planes affected by drawing, clearing and scrolling
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & ((1 << PLANE_COUNT) - 1);
     }
 
-    pub fn get_index_from_coords(x: usize, y: usize) -> usize {
-        y * WIDTH + x
+    pub fn get_planes(&self) -> u8 {
+        self.planes
     }
 
-    pub fn draw_byte(&mut self, byte: u8, x: u8, y: u8) -> bool {
+    /// Draw a byte on a single plane at the given coordinates, returning whether any pixel was erased.
+    /// Pixels falling off the screen are dropped if `clip` is set, otherwise they wrap
+    pub fn draw_byte(&mut self, byte: u8, x: u8, y: u8, clip: bool, plane: u8) -> bool {
+        let width = self.get_width();
+        let height = self.get_height();
         let mut erased = false;
         let mut pos_x = x as usize;
         let mut pos_y = y as usize;
         let mut b = byte;
 
         for _ in 0..8 {
-            pos_x %= WIDTH;
-            pos_y %= HEIGHT;
-            let index = Display::get_index_from_coords(pos_x, pos_y);
-            let bit = (b & 0x80) >> 7;
-            let prev_value = self.screen[index];
-            self.screen[index] ^= bit;
-
-            if prev_value == 1 && self.screen[index] == 0 {
-                erased = true;
+            if clip && (pos_x >= width || pos_y >= height) {
+                break;
+            }
+            pos_x %= width;
+            pos_y %= height;
+            let index = self.get_index_from_coords(pos_x, pos_y);
+            if b & 0x80 != 0 {
+                if self.screen[index] & plane != 0 {
+                    erased = true;
+                }
+                self.screen[index] ^= plane;
             }
 
             pos_x += 1;
@@ -39,13 +88,154 @@ impl Display {
         erased
     }
 
+    /// Scroll the selected planes down by `rows` pixels
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize)
+    }
+
+    /// Scroll the selected planes up by `rows` pixels
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize))
+    }
+
+    /// Scroll the selected planes right by `columns` pixels
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0)
+    }
+
+    /// Scroll the selected planes left by `columns` pixels
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0)
+    }
+
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.get_width() as isize;
+        let height = self.get_height() as isize;
+        let previous = self.screen;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let value = if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                    previous[(src_y * width + src_x) as usize] & self.planes
+                } else {
+                    0
+                };
+                let index = (y * width + x) as usize;
+                self.screen[index] = (self.screen[index] & !self.planes) | value;
+            }
+        }
+    }
+
+    /// Clear the selected planes
     pub fn clear(&mut self) {
         for pixel in self.screen.iter_mut() {
-            *pixel = 0;
+            *pixel &= !self.planes;
+        }
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.hires);
+        writer.write_u8(self.planes);
+        writer.write_bytes(&self.screen);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.hires = reader.read_bool()?;
+        self.planes = reader.read_u8()?;
+        if self.planes >= 1 << PLANE_COUNT {
+            return Err(StateError::InvalidValue("plane mask"));
         }
+        reader.read_into(&mut self.screen)
     }
 
+    /// Get the pixels of the current resolution, one byte per pixel in rows of `get_width()`
     pub fn get_display_buffer(&self) -> &[u8] {
-        &self.screen
+        &self.screen[..self.get_width() * self.get_height()]
     }
-}
\ No newline at end of file
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_hires_buffer_size() {
+        let mut display = Display::new();
+        assert_eq!(display.get_display_buffer().len(), WIDTH * HEIGHT);
+
+        display.set_hires(true);
+        assert_eq!(display.get_display_buffer().len(), HIRES_WIDTH * HIRES_HEIGHT);
+    }
+
+    #[test]
+    pub fn test_scroll_down() {
+        let mut display = Display::new();
+        display.draw_byte(0x80, 3, 0, true, 1);
+
+        display.scroll_down(2);
+
+        assert_eq!(display.screen[display.get_index_from_coords(3, 0)], 0);
+        assert_eq!(display.screen[display.get_index_from_coords(3, 2)], 1);
+    }
+
+    #[test]
+    pub fn test_scroll_right() {
+        let mut display = Display::new();
+        display.set_hires(true);
+        display.draw_byte(0x80, HIRES_WIDTH as u8 - 2, 1, true, 1);
+        display.draw_byte(0x80, 0, 1, true, 1);
+
+        display.scroll_right(4);
+
+        assert_eq!(display.get_display_buffer().iter().filter(|pixel| **pixel == 1).count(), 1);
+        assert_eq!(display.screen[display.get_index_from_coords(4, 1)], 1);
+    }
+
+    #[test]
+    pub fn test_scroll_left() {
+        let mut display = Display::new();
+        display.draw_byte(0x80, 1, 5, true, 1);
+        display.draw_byte(0x80, 9, 5, true, 1);
+
+        display.scroll_left(4);
+
+        assert_eq!(display.get_display_buffer().iter().filter(|pixel| **pixel == 1).count(), 1);
+        assert_eq!(display.screen[display.get_index_from_coords(5, 5)], 1);
+    }
+
+    #[test]
+    pub fn test_draw_on_second_plane() {
+        let mut display = Display::new();
+
+        assert!(!display.draw_byte(0xc0, 0, 0, true, 1));
+        assert!(!display.draw_byte(0x80, 0, 0, true, 2));
+        assert_eq!(display.screen[0], 3);
+        assert_eq!(display.screen[1], 1);
+
+        assert!(display.draw_byte(0x80, 0, 0, true, 2));
+        assert_eq!(display.screen[0], 1);
+    }
+
+    #[test]
+    pub fn test_clear_and_scroll_selected_planes() {
+        let mut display = Display::new();
+        display.draw_byte(0x80, 0, 0, true, 1);
+        display.draw_byte(0x80, 0, 0, true, 2);
+
+        display.set_planes(2);
+        display.scroll_right(1);
+        assert_eq!(display.screen[0], 1);
+        assert_eq!(display.screen[1], 2);
+
+        display.clear();
+        assert_eq!(display.screen[0], 1);
+        assert_eq!(display.screen[1], 0);
+    }
+}
@@ -0,0 +1,580 @@
+//! A GDB remote serial protocol server, so GDB or another front-end speaking
+//! the protocol can debug a running `Chip8` over TCP
+//!
+//! ```text
+//! chip8 ROM --gdb 6502
+//! (gdb) target remote localhost:6502
+//! ```
+//!
+//! The client fetches the register layout with `qXfer:features:read:target.xml`.
+//!
+//! Registers are numbered as in `TARGET_XML`: V0 to VF are 0 to 15, then I, PC,
+//! SP, DT and ST. Multi-byte registers are sent little-endian. Breakpoints
+//! (`Z0`/`Z1`) use the `Debugger`, write and read watchpoints (`Z2`/`Z3`) the `Bus`.
+
+use crate::chip8::Chip8;
+use crate::debugger::{Debugger, StopReason};
+use crate::watchpoint::{WatchKind, Watchpoint};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+/// Target description sent to the client, describing the register file
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Size of each register in bytes, in register number order
+const REGISTER_SIZES: [usize; 21] = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1];
+
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+const REGISTER_DT: usize = 19;
+const REGISTER_ST: usize = 20;
+
+/// Byte sent by the client to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+/// Stop replies, with the POSIX signal the stop is reported as
+const STOP_TRAP: &str = "S05";
+const STOP_INTERRUPT: &str = "S02";
+const STOP_CRASH: &str = "S04";
+const STOP_EXITED: &str = "W00";
+
+/// Serves one client connected over `stream`
+pub struct GdbStub<S: Read + Write> {
+    stream: S,
+    debugger: Debugger,
+    instructions_per_frame: usize,
+    /// Bytes received but not yet handled
+    input: Vec<u8>,
+    /// The client continued and is waiting for a stop reply
+    running: bool,
+    /// The client interrupted the running machine
+    interrupted: bool,
+    /// Acknowledgements are turned off with QStartNoAckMode
+    ack: bool,
+    connected: bool,
+}
+
+impl GdbStub<TcpStream> {
+
+    /// Wait for a client to connect. The connection is made non-blocking so
+    /// `poll` can be called once per frame
+    pub fn accept(listener: &TcpListener, instructions_per_frame: usize) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(GdbStub::new(stream, instructions_per_frame))
+    }
+}
+
+impl<S: Read + Write> GdbStub<S> {
+
+    /// Serve a client over a non-blocking stream, starting with the machine stopped
+    pub fn new(stream: S, instructions_per_frame: usize) -> Self {
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        GdbStub { stream, debugger, instructions_per_frame, input: Vec::new(), running: false, interrupted: false, ack: true, connected: true }
+    }
+
+    /// Whether the client let the machine run
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Handle the packets received so far and run one frame if the client
+    /// continued. Returns false once the client detached or disconnected
+    pub fn poll(&mut self, chip8: &mut Chip8) -> io::Result<bool> {
+        self.receive()?;
+        while self.connected {
+            match self.next_packet() {
+                Some(Ok(packet)) => {
+                    if self.ack {
+                        self.write(b"+")?;
+                    }
+                    self.handle(&packet, chip8)?;
+                }
+                Some(Err(())) => self.write(b"-")?,
+                None => break,
+            }
+        }
+        if self.interrupted {
+            self.interrupted = false;
+            self.send(STOP_INTERRUPT)?;
+        }
+
+        if self.running && self.connected {
+            let reply = match self.debugger.run_frame(chip8, self.instructions_per_frame) {
+                Ok(Some(StopReason::Watchpoint { hit, .. })) => {
+                    let kind = if hit.kind == WatchKind::Read { "rwatch" } else { "watch" };
+                    Some(format!("T05{}:{:x};", kind, hit.address))
+                }
+                Ok(Some(_)) => Some(STOP_TRAP.to_string()),
+                Ok(None) if chip8.has_exited() => Some(STOP_EXITED.to_string()),
+                Ok(None) => None,
+                Err(_) => Some(STOP_CRASH.to_string()),
+            };
+            if let Some(reply) = reply {
+                self.running = false;
+                self.debugger.pause();
+                self.send(&reply)?;
+            }
+        }
+
+        Ok(self.connected)
+    }
+
+    /// Read whatever has arrived without blocking
+    fn receive(&mut self) -> io::Result<()> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.connected = false;
+                    return Ok(());
+                }
+                Ok(count) => self.input.extend_from_slice(&buffer[..count]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Take the next complete packet from the input, `Err` if its checksum is wrong.
+    /// Interrupts and acknowledgements in between are handled here
+    fn next_packet(&mut self) -> Option<Result<String, ()>> {
+        loop {
+            match *self.input.first()? {
+                b'$' => break,
+                INTERRUPT => {
+                    self.input.remove(0);
+                    if self.running {
+                        self.running = false;
+                        self.interrupted = true;
+                        self.debugger.pause();
+                    }
+                }
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+
+        let end = self.input.iter().position(|byte| *byte == b'#')?;
+        if self.input.len() < end + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+        if checksum != Some(checksum_of(data)) {
+            return Some(Err(()));
+        }
+        Some(String::from_utf8(data.to_vec()).map_err(|_| ()))
+    }
+
+    fn handle(&mut self, packet: &str, chip8: &mut Chip8) -> io::Result<()> {
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            // Ask why the target stopped
+            "?" => STOP_TRAP.to_string(),
+            "g" => {
+                (0..REGISTER_SIZES.len()).map(|register| encode_register(chip8, register)).collect()
+            }
+            "G" => {
+                let mut offset = 0;
+                let mut reply = "OK".to_string();
+                for (register, size) in REGISTER_SIZES.iter().enumerate() {
+                    let hex = arguments.get(offset..offset + size * 2);
+                    if hex.and_then(|hex| write_register(chip8, register, hex)).is_none() {
+                        reply = "E01".to_string();
+                        break;
+                    }
+                    offset += size * 2;
+                }
+                reply
+            }
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(register) if register < REGISTER_SIZES.len() => encode_register(chip8, register),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let written = arguments.split_once('=').and_then(|(register, hex)| {
+                    let register = usize::from_str_radix(register, 16).ok().filter(|register| *register < REGISTER_SIZES.len())?;
+                    write_register(chip8, register, hex)
+                });
+                if written.is_some() { "OK".to_string() } else { "E01".to_string() }
+            }
+            "m" => match parse_range(arguments, chip8) {
+                Some((address, length)) => {
+                    (address..address + length).map(|address| format!("{:02x}", chip8.bus.memory_peek_byte(address as u16))).collect()
+                }
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let written = arguments.split_once(':').and_then(|(range, hex)| {
+                    let (address, length) = parse_range(range, chip8)?;
+                    let bytes = decode_hex(hex).filter(|bytes| bytes.len() == length)?;
+                    for (offset, byte) in bytes.into_iter().enumerate() {
+                        chip8.bus.memory_write_byte((address + offset) as u16, byte);
+                    }
+                    Some(())
+                });
+                if written.is_some() { "OK".to_string() } else { "E01".to_string() }
+            }
+            "c" | "s" => {
+                if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                    chip8.cpu.set_pc(address);
+                }
+                if command == "c" {
+                    self.debugger.resume();
+                    self.running = true;
+                    return Ok(());
+                }
+                match self.debugger.step(chip8) {
+                    Ok(_) if chip8.has_exited() => STOP_EXITED.to_string(),
+                    Ok(_) => STOP_TRAP.to_string(),
+                    Err(_) => STOP_CRASH.to_string(),
+                }
+            }
+            "Z" | "z" => self.handle_breakpoint(command == "Z", arguments, chip8),
+            "q" | "Q" => self.handle_query(packet),
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                self.send("OK")?;
+                self.connected = false;
+                return Ok(());
+            }
+            "k" => {
+                self.connected = false;
+                return Ok(());
+            }
+            // An empty reply tells the client the packet isn't supported
+            _ => String::new(),
+        };
+        self.send(&reply)
+    }
+
+    /// `Z`/`z` packets: `TYPE,ADDRESS,KIND`
+    fn handle_breakpoint(&mut self, insert: bool, arguments: &str, chip8: &mut Chip8) -> String {
+        let mut fields = arguments.split(',');
+        let kind = fields.next();
+        let address = match fields.next().and_then(|address| u16::from_str_radix(address, 16).ok()) {
+            Some(address) => address,
+            None => return "E01".to_string(),
+        };
+        let length = fields.next().and_then(|length| u16::from_str_radix(length, 16).ok()).unwrap_or(1).max(1);
+
+        let watchpoint = |kind| Watchpoint::new(address, address.saturating_add(length - 1), kind);
+        match (kind, insert) {
+            (Some("0") | Some("1"), true) => self.debugger.add_breakpoint(address),
+            (Some("0") | Some("1"), false) => {
+                self.debugger.remove_breakpoint(address);
+            }
+            (Some("2"), true) => chip8.bus.add_watchpoint(watchpoint(WatchKind::Write)),
+            (Some("3"), true) => chip8.bus.add_watchpoint(watchpoint(WatchKind::Read)),
+            (Some("2"), false) => {
+                chip8.bus.remove_watchpoint(watchpoint(WatchKind::Write));
+            }
+            (Some("3"), false) => {
+                chip8.bus.remove_watchpoint(watchpoint(WatchKind::Read));
+            }
+            _ => return String::new(),
+        }
+        "OK".to_string()
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match range.split_once(',') {
+                Some((offset, length)) => (usize::from_str_radix(offset, 16), usize::from_str_radix(length, 16)),
+                None => return "E01".to_string(),
+            };
+            return match (offset, length) {
+                (Ok(offset), Ok(length)) if offset <= TARGET_XML.len() => match offset.checked_add(length) {
+                    Some(end) => {
+                        let end = end.min(TARGET_XML.len());
+                        let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                        format!("{}{}", marker, &TARGET_XML[offset..end])
+                    }
+                    None => "E01".to_string(),
+                },
+                _ => "E01".to_string(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.ack = false;
+                "OK".to_string()
+            }
+            // Attached to an existing process, so quitting detaches rather than kills
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.write(packet.as_bytes())
+    }
+
+    /// Write everything, waiting while a non-blocking stream is full
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            match self.stream.write(data) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(count) => data = &data[count..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.stream.flush()
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok()).collect()
+}
+
+/// Parse `ADDRESS,LENGTH`, which must lie within memory
+fn parse_range(range: &str, chip8: &Chip8) -> Option<(usize, usize)> {
+    let (address, length) = range.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+    if address.checked_add(length)? > chip8.bus.memory_get_size() {
+        return None;
+    }
+    Some((address, length))
+}
+
+fn read_register(chip8: &Chip8, register: usize) -> u16 {
+    match register {
+        REGISTER_I => chip8.cpu.get_i(),
+        REGISTER_PC => chip8.cpu.get_pc(),
+        REGISTER_SP => chip8.cpu.get_sp() as u16,
+        REGISTER_DT => chip8.bus.get_delay_timer() as u16,
+        REGISTER_ST => chip8.bus.get_sound_timer() as u16,
+        _ => chip8.cpu.read_reg(register as u8) as u16,
+    }
+}
+
+fn encode_register(chip8: &Chip8, register: usize) -> String {
+    let value = read_register(chip8, register);
+    value.to_le_bytes()[..REGISTER_SIZES[register]].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Write a register from little-endian hex. SP can't be changed as the stack
+/// contents would no longer match it
+fn write_register(chip8: &mut Chip8, register: usize, hex: &str) -> Option<()> {
+    let bytes = decode_hex(hex).filter(|bytes| bytes.len() == REGISTER_SIZES[register])?;
+    let value = bytes.iter().rev().fold(0u16, |value, byte| value << 8 | *byte as u16);
+    match register {
+        REGISTER_I => chip8.cpu.set_i(value),
+        REGISTER_PC => chip8.cpu.set_pc(value),
+        REGISTER_SP if value != chip8.cpu.get_sp() as u16 => return None,
+        REGISTER_SP => {}
+        REGISTER_DT => chip8.bus.set_delay_timer(value as u8),
+        REGISTER_ST => chip8.bus.set_sound_timer(value as u8),
+        _ => chip8.cpu.write_reg(register as u8, value as u8),
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufRead;
+    use std::io::BufReader;
+
+    /// A scripted client speaking to the stub over a local TCP connection
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+
+        fn send_raw(&mut self, data: &[u8]) {
+            self.writer.write_all(data).unwrap();
+        }
+
+        /// Send a packet and return the reply's data, checking the acknowledgement and checksum
+        fn request(&mut self, data: &str) -> String {
+            self.send_raw(format!("${}#{:02x}", data, checksum_of(data.as_bytes())).as_bytes());
+            let mut ack = [0];
+            self.reader.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            let mut packet = Vec::new();
+            self.reader.read_until(b'#', &mut packet).unwrap();
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum).unwrap();
+            assert_eq!(packet[0], b'$');
+            let data = &packet[1..packet.len() - 1];
+            assert_eq!(u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(), checksum_of(data));
+            String::from_utf8(data.to_vec()).unwrap()
+        }
+    }
+
+    /// Serve `ROM` to a client running `script` on another thread, returning
+    /// the machine once the client has gone
+    fn serve<F: FnOnce(Client) + Send + 'static>(script: F) -> Chip8 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            script(Client { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream });
+        });
+
+        let mut chip8 = Chip8::builder().seed(0).rom(&ROM).build();
+        let mut stub = GdbStub::accept(&listener, 8).unwrap();
+        while stub.poll(&mut chip8).unwrap() {
+            if !stub.is_running() {
+                thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+        client.join().unwrap();
+        chip8
+    }
+
+    /// V0 = 0x2A, I = 0x300, save v0, loop forever
+    const ROM: [u8; 8] = [0x60, 0x2A, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+
+    #[test]
+    pub fn test_registers_and_memory() {
+        let chip8 = serve(|mut client| {
+            assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+            assert_eq!(client.request("?"), "S05");
+            assert_eq!(client.request("s"), "S05");
+            assert_eq!(client.request("p0"), "2a");
+            assert_eq!(client.request("p11"), "0202");
+
+            // V0-VF, I, PC, SP, DT, ST
+            let registers = client.request("g");
+            assert_eq!(registers.len(), (16 + 2 + 2 + 3) * 2);
+            assert_eq!(&registers[32..], "00000202000000");
+
+            assert_eq!(client.request("P10=3412"), "OK");
+            assert_eq!(client.request("p10"), "3412");
+            assert_eq!(client.request("P12=05"), "E01");
+            assert_eq!(client.request("P13=3c"), "OK");
+
+            assert_eq!(client.request("m200,4"), "602aa300");
+            assert_eq!(client.request("M400,2:beef"), "OK");
+            assert_eq!(client.request("m400,2"), "beef");
+            assert_eq!(client.request("mffff,2"), "E01");
+            assert_eq!(client.request("m1,ffffffffffffffff"), "E01");
+            assert_eq!(client.request("M1,ffffffffffffffff:00"), "E01");
+            assert_eq!(client.request("vMustReplyEmpty"), "");
+            assert_eq!(client.request("D"), "OK");
+        });
+
+        assert_eq!(chip8.cpu.get_i(), 0x1234);
+        assert_eq!(chip8.bus.get_delay_timer(), 0x3c);
+        assert_eq!(chip8.bus.memory_read_byte(0x401), 0xef);
+    }
+
+    #[test]
+    pub fn test_breakpoints_and_watchpoints() {
+        serve(|mut client| {
+            assert_eq!(client.request("Z0,204,2"), "OK");
+            assert_eq!(client.request("c"), "S05");
+            assert_eq!(client.request("p11"), "0402");
+
+            assert_eq!(client.request("z0,204,2"), "OK");
+            assert_eq!(client.request("Z2,300,1"), "OK");
+            assert_eq!(client.request("c"), "T05watch:300;");
+            assert_eq!(client.request("p11"), "0602");
+
+            // Removing a read watchpoint leaves the write watchpoint on the same address
+            assert_eq!(client.request("Z3,300,1"), "OK");
+            assert_eq!(client.request("z3,300,1"), "OK");
+            assert_eq!(client.request("c202"), "T05watch:300;");
+
+            // The loop runs until interrupted
+            assert_eq!(client.request("z2,300,1"), "OK");
+            client.send_raw(b"$c#63");
+            let mut ack = [0];
+            client.reader.read_exact(&mut ack).unwrap();
+            client.send_raw(&[INTERRUPT]);
+            assert_eq!(client.reply(), "S02");
+
+            client.send_raw(b"$k#6b");
+        });
+    }
+
+    #[test]
+    pub fn test_target_description() {
+        serve(|mut client| {
+            let mut xml = String::new();
+            loop {
+                let reply = client.request(&format!("qXfer:features:read:target.xml:{:x},40", xml.len()));
+                xml.push_str(&reply[1..]);
+                if reply.starts_with('l') {
+                    break;
+                }
+            }
+            assert_eq!(xml, TARGET_XML);
+            assert_eq!(client.request("qXfer:features:read:target.xml:1,ffffffffffffffff"), "E01");
+            assert_eq!(TARGET_XML.matches("<reg ").count(), REGISTER_SIZES.len());
+            client.request("D");
+        });
+    }
+
+    #[test]
+    pub fn test_bad_checksum_is_rejected() {
+        serve(|mut client| {
+            client.send_raw(b"$g#00");
+            let mut nak = [0];
+            client.reader.read_exact(&mut nak).unwrap();
+            assert_eq!(nak[0], b'-');
+            client.request("D");
+        });
+    }
+}
@@ -0,0 +1,268 @@
+//! Running ROMs without a window, for automated tests and CI
+
+use crate::chip8::Chip8;
+use crate::cpu::{CpuError, StepOutcome};
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "png")]
+use std::io;
+
+/// Characters used for each combination of the two XO-CHIP planes in text dumps
+pub const TEXT_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+/// How long a headless run lasts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunLimit {
+    /// Run this many 60 Hz frames
+    Frames(u64),
+    /// Run this many instructions, still ticking the timers once per frame
+    Cycles(u64),
+}
+
+/// A key of the hex keypad pressed or released at the start of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Scripted key input, written as `FRAME+KEY` to press and `FRAME-KEY` to release
+/// a hex key, separated by commas or whitespace, e.g. `60+5, 70-5`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+}
+
+/// A key script event which couldn't be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    pub token: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid key event {:?}, expected FRAME+KEY or FRAME-KEY", self.token)
+    }
+}
+
+impl Error for ScriptError {}
+
+impl KeyScript {
+
+    pub fn parse(script: &str) -> Result<Self, ScriptError> {
+        let mut events = Vec::new();
+        for token in script.split(|c: char| c == ',' || c.is_whitespace()).filter(|token| !token.is_empty()) {
+            events.push(KeyScript::parse_event(token).ok_or_else(|| ScriptError { token: token.to_string() })?);
+        }
+        // Events of the same frame keep their written order
+        events.sort_by_key(|event| event.frame);
+        Ok(KeyScript { events })
+    }
+
+    fn parse_event(token: &str) -> Option<KeyEvent> {
+        let split = token.find(['+', '-'])?;
+        let frame = token[..split].parse().ok()?;
+        let pressed = &token[split..split + 1] == "+";
+        let key_text = &token[split + 1..];
+        if key_text.len() != 1 {
+            return None;
+        }
+        let key = u8::from_str_radix(key_text, 16).ok()?;
+        Some(KeyEvent { frame, key, pressed })
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// Press and release the keys scheduled for the start of `frame`
+    pub fn apply(&self, frame: u64, chip8: &mut Chip8) {
+        for event in self.events.iter().filter(|event| event.frame == frame) {
+            if event.pressed {
+                chip8.key_down(event.key);
+            }
+            else {
+                chip8.key_up(event.key);
+            }
+        }
+    }
+}
+
+/// What a headless run did before stopping
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunSummary {
+    pub frames: u64,
+    pub cycles: u64,
+    /// The program ran the SUPER-CHIP exit instruction before the limit was reached
+    pub exited: bool,
+}
+
+/// Runs a ROM for a fixed length with scripted input
+#[derive(Clone, Debug)]
+pub struct HeadlessRunner {
+    pub limit: RunLimit,
+    pub instructions_per_frame: usize,
+    pub script: KeyScript,
+}
+
+impl HeadlessRunner {
+
+    pub fn new(limit: RunLimit, instructions_per_frame: usize) -> Self {
+        HeadlessRunner { limit, instructions_per_frame, script: KeyScript::default() }
+    }
+
+    /// Run until the limit is reached or the program exits. On a CPU error the
+    /// machine is left as it crashed, so its screen can still be dumped
+    pub fn run(&self, chip8: &mut Chip8) -> Result<RunSummary, CpuError> {
+        let mut summary = RunSummary { frames: 0, cycles: 0, exited: false };
+        // No instructions per frame would never reach a cycle limit
+        let instructions_per_frame = self.instructions_per_frame.max(1);
+
+        while !self.limit_reached(&summary) {
+            self.script.apply(summary.frames, chip8);
+            let mut instructions_run = 0;
+            while chip8.frame_has_room(instructions_run, instructions_per_frame) {
+                if self.limit_reached(&summary) {
+                    break;
+                }
+                instructions_run += 1;
+                match chip8.run_instruction()? {
+                    StepOutcome::Exited => {
+                        summary.cycles += 1;
+                        summary.exited = true;
+                        return Ok(summary);
+                    }
+                    // The DXYN didn't run, it's retried next frame
+                    StepOutcome::WaitingForVblank => break,
+                    _ => summary.cycles += 1,
+                }
+            }
+            chip8.tick_timers();
+            summary.frames += 1;
+        }
+
+        Ok(summary)
+    }
+
+    fn limit_reached(&self, summary: &RunSummary) -> bool {
+        match self.limit {
+            RunLimit::Frames(frames) => summary.frames >= frames,
+            RunLimit::Cycles(cycles) => summary.cycles >= cycles,
+        }
+    }
+}
+
+/// Dump the screen as one line of `TEXT_PIXELS` per row
+pub fn display_to_text(chip8: &Chip8) -> String {
+    let width = chip8.get_display_width();
+    let mut text = String::new();
+    for row in chip8.get_display_buffer().chunks(width) {
+        text.extend(row.iter().map(|pixel| TEXT_PIXELS[*pixel as usize & 3]));
+        text.push('\n');
+    }
+    text
+}
+
+/// Write the screen as an RGB PNG, one image pixel per CHIP-8 pixel
+#[cfg(feature = "png")]
+pub fn write_png<W: io::Write>(chip8: &Chip8, palette: &[u32; 4], writer: W) -> io::Result<()> {
+    let width = chip8.get_display_width();
+    let height = chip8.get_display_buffer().len() / width;
+    let mut data = Vec::with_capacity(width * height * 3);
+    for pixel in chip8.get_display_buffer() {
+        let colour = palette[*pixel as usize & 3];
+        data.extend_from_slice(&[(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]);
+    }
+
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::DRAW_TWICE_PROGRAM;
+    use crate::quirks::Quirks;
+
+    #[test]
+    pub fn test_parse_script() {
+        let script = KeyScript::parse("70-5, 60+5\n60+a").unwrap();
+        assert_eq!(script.events(), &[
+            KeyEvent { frame: 60, key: 0x5, pressed: true },
+            KeyEvent { frame: 60, key: 0xA, pressed: true },
+            KeyEvent { frame: 70, key: 0x5, pressed: false },
+        ]);
+
+        assert_eq!(KeyScript::parse("60+5 60*5").unwrap_err().token, "60*5");
+        assert!(KeyScript::parse("60+10").is_err());
+        assert!(KeyScript::parse("+5").is_err());
+    }
+
+    #[test]
+    pub fn test_run_frames_with_script() {
+        // V0 = K, V1 += 1, loop forever
+        let data: Vec<u8> = vec![0xF0, 0x0A, 0x71, 0x01, 0x12, 0x02];
+        let mut chip8 = Chip8::builder().rom(&data).build();
+        let mut runner = HeadlessRunner::new(RunLimit::Frames(10), 4);
+        runner.script = KeyScript::parse("2+7 3-7").unwrap();
+
+        let summary = runner.run(&mut chip8).unwrap();
+
+        assert_eq!(summary, RunSummary { frames: 10, cycles: 40, exited: false });
+        assert_eq!(chip8.cpu.read_reg(0), 7);
+        assert!(chip8.cpu.read_reg(1) > 0);
+    }
+
+    #[test]
+    pub fn test_run_cycles() {
+        let mut chip8 = Chip8::builder().rom(&[0x12, 0x00]).build();
+        let summary = HeadlessRunner::new(RunLimit::Cycles(10), 4).run(&mut chip8).unwrap();
+        assert_eq!(summary, RunSummary { frames: 3, cycles: 10, exited: false });
+
+        // Zero instructions per frame still runs one each frame
+        let summary = HeadlessRunner::new(RunLimit::Cycles(3), 0).run(&mut chip8).unwrap();
+        assert_eq!(summary, RunSummary { frames: 3, cycles: 3, exited: false });
+    }
+
+    #[test]
+    pub fn test_waiting_draws_are_not_counted() {
+        let mut chip8 = Chip8::builder().rom(&DRAW_TWICE_PROGRAM).build();
+        let summary = HeadlessRunner::new(RunLimit::Frames(2), 8).run(&mut chip8).unwrap();
+        assert_eq!(summary, RunSummary { frames: 2, cycles: 3, exited: false });
+    }
+
+    #[test]
+    pub fn test_run_stops_on_exit() {
+        let mut chip8 = Chip8::builder().quirks(Quirks::superchip()).rom(&[0x00, 0xE0, 0x00, 0xFD]).build();
+        let summary = HeadlessRunner::new(RunLimit::Frames(100), 8).run(&mut chip8).unwrap();
+        assert_eq!(summary, RunSummary { frames: 0, cycles: 2, exited: true });
+    }
+
+    #[test]
+    pub fn test_display_to_text() {
+        // I = font "0", draw it at (0, 0)
+        let mut chip8 = Chip8::builder().rom(&[0xA0, 0x00, 0xD0, 0x05]).build();
+        HeadlessRunner::new(RunLimit::Cycles(2), 8).run(&mut chip8).unwrap();
+
+        let text = display_to_text(&chip8);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(&lines[0][..8], "####....");
+        assert_eq!(&lines[1][..8], "#..#....");
+        assert_eq!(lines[1].len(), 64);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    pub fn test_write_png() {
+        let chip8 = Chip8::builder().build();
+        let mut data = Vec::new();
+        write_png(&chip8, &crate::display::PALETTE, &mut data).unwrap();
+        assert_eq!(&data[1..4], b"PNG");
+    }
+}
@@ -0,0 +1,488 @@
+use crate::quirks::InstructionSet;
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction. `x` and `y` are register
+/// indices, `kk` bytes, `n` nibbles and `nnn` 12 bit addresses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN: call a machine code routine, run as a jump
+    Sys(u16),
+    /// 00CN: scroll the display down n pixels
+    ScrollDown(u8),
+    /// 00DN: scroll the display up n pixels
+    ScrollUp(u8),
+    /// 00E0: clear the display
+    Clear,
+    /// 00EE: return from a subroutine
+    Return,
+    /// 00FB: scroll the display right 4 pixels
+    ScrollRight,
+    /// 00FC: scroll the display left 4 pixels
+    ScrollLeft,
+    /// 00FD: exit the interpreter
+    Exit,
+    /// 00FE: switch to the low resolution screen
+    Lores,
+    /// 00FF: switch to the high resolution screen
+    Hires,
+    /// 1NNN: jump to nnn
+    Jump(u16),
+    /// 2NNN: call the subroutine at nnn
+    Call(u16),
+    /// 3XKK: skip the next instruction if Vx == kk
+    SkipEqualByte(u8, u8),
+    /// 4XKK: skip the next instruction if Vx != kk
+    SkipNotEqualByte(u8, u8),
+    /// 5XY0: skip the next instruction if Vx == Vy
+    SkipEqual(u8, u8),
+    /// 5XY2: store Vx through Vy at I
+    SaveRange(u8, u8),
+    /// 5XY3: load Vx through Vy from I
+    LoadRange(u8, u8),
+    /// 6XKK: Vx = kk
+    LoadByte(u8, u8),
+    /// 7XKK: Vx = Vx + kk
+    AddByte(u8, u8),
+    /// 8XY0: Vx = Vy
+    Move(u8, u8),
+    /// 8XY1: Vx = Vx | Vy
+    Or(u8, u8),
+    /// 8XY2: Vx = Vx & Vy
+    And(u8, u8),
+    /// 8XY3: Vx = Vx ^ Vy
+    Xor(u8, u8),
+    /// 8XY4: Vx = Vx + Vy, VF = carry
+    Add(u8, u8),
+    /// 8XY5: Vx = Vx - Vy, VF = NOT borrow
+    Sub(u8, u8),
+    /// 8XY6: Vx = Vy (or Vx) >> 1, VF = shifted out bit
+    ShiftRight(u8, u8),
+    /// 8XY7: Vx = Vy - Vx, VF = NOT borrow
+    SubReverse(u8, u8),
+    /// 8XYE: Vx = Vy (or Vx) << 1, VF = shifted out bit
+    ShiftLeft(u8, u8),
+    /// 9XY0: skip the next instruction if Vx != Vy
+    SkipNotEqual(u8, u8),
+    /// ANNN: I = nnn
+    LoadIndex(u16),
+    /// BNNN: jump to nnn + V0 (or xnn + Vx)
+    JumpOffset(u16),
+    /// CXKK: Vx = random byte & kk
+    Random(u8, u8),
+    /// DXYN: draw an n byte sprite from I at (Vx, Vy), VF = collision
+    Draw(u8, u8, u8),
+    /// EX9E: skip the next instruction if the key Vx is pressed
+    SkipKeyPressed(u8),
+    /// EXA1: skip the next instruction if the key Vx is not pressed
+    SkipKeyNotPressed(u8),
+    /// F000 NNNN: I = the 16 bit address in the word following the opcode
+    LoadLongIndex,
+    /// FN01: select the drawing planes n
+    SelectPlanes(u8),
+    /// F002: load the 16 byte audio pattern at I
+    LoadAudioPattern,
+    /// FX07: Vx = delay timer
+    GetDelay(u8),
+    /// FX0A: wait for a key press and release, Vx = key
+    WaitKey(u8),
+    /// FX15: delay timer = Vx
+    SetDelay(u8),
+    /// FX18: sound timer = Vx
+    SetSound(u8),
+    /// FX1E: I = I + Vx
+    AddIndex(u8),
+    /// FX29: I = address of the small font digit Vx
+    LoadFont(u8),
+    /// FX30: I = address of the big font digit Vx
+    LoadBigFont(u8),
+    /// FX33: store the BCD of Vx at I, I + 1 and I + 2
+    StoreBcd(u8),
+    /// FX3A: audio pattern pitch = Vx
+    SetPitch(u8),
+    /// FX55: store V0 through Vx at I
+    Store(u8),
+    /// FX65: load V0 through Vx from I
+    Load(u8),
+    /// FX75: store V0 through Vx in the RPL user flags
+    SaveFlags(u8),
+    /// FX85: load V0 through Vx from the RPL user flags
+    LoadFlags(u8),
+}
+
+/// Mnemonic styles for printing instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// Cowgod's Chip-8 Technical Reference, e.g. `LD V0, 0x2A`
+    Cowgod,
+    /// Octo assembly language, e.g. `v0 := 0x2A`
+    Octo,
+}
+
+/// Decode an opcode, accepting every instruction up to XO-CHIP. Returns None
+/// for opcodes which aren't instructions of any supported machine
+pub fn decode(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    let nnn = opcode & 0xfff;
+    let n = (opcode & 0xf) as u8;
+    let x = ((opcode & 0x0f00) >> 8) as u8;
+    let y = ((opcode & 0x00f0) >> 4) as u8;
+    let kk = (opcode & 0xff) as u8;
+
+    let instruction = match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xC, _) => ScrollDown(n),
+        (0x0, 0x0, 0xD, _) => ScrollUp(n),
+        (0x0, 0x0, 0xE, 0x0) => Clear,
+        (0x0, 0x0, 0xE, 0xE) => Return,
+        (0x0, 0x0, 0xF, 0xB) => ScrollRight,
+        (0x0, 0x0, 0xF, 0xC) => ScrollLeft,
+        (0x0, 0x0, 0xF, 0xD) => Exit,
+        (0x0, 0x0, 0xF, 0xE) => Lores,
+        (0x0, 0x0, 0xF, 0xF) => Hires,
+        (0x0, _, _, _) => Sys(nnn),
+        (0x1, _, _, _) => Jump(nnn),
+        (0x2, _, _, _) => Call(nnn),
+        (0x3, _, _, _) => SkipEqualByte(x, kk),
+        (0x4, _, _, _) => SkipNotEqualByte(x, kk),
+        (0x5, _, _, 0x0) => SkipEqual(x, y),
+        (0x5, _, _, 0x2) => SaveRange(x, y),
+        (0x5, _, _, 0x3) => LoadRange(x, y),
+        (0x6, _, _, _) => LoadByte(x, kk),
+        (0x7, _, _, _) => AddByte(x, kk),
+        (0x8, _, _, 0x0) => Move(x, y),
+        (0x8, _, _, 0x1) => Or(x, y),
+        (0x8, _, _, 0x2) => And(x, y),
+        (0x8, _, _, 0x3) => Xor(x, y),
+        (0x8, _, _, 0x4) => Add(x, y),
+        (0x8, _, _, 0x5) => Sub(x, y),
+        (0x8, _, _, 0x6) => ShiftRight(x, y),
+        (0x8, _, _, 0x7) => SubReverse(x, y),
+        (0x8, _, _, 0xE) => ShiftLeft(x, y),
+        (0x9, _, _, 0x0) => SkipNotEqual(x, y),
+        (0xA, _, _, _) => LoadIndex(nnn),
+        (0xB, _, _, _) => JumpOffset(nnn),
+        (0xC, _, _, _) => Random(x, kk),
+        (0xD, _, _, _) => Draw(x, y, n),
+        (0xE, _, 0x9, 0xE) => SkipKeyPressed(x),
+        (0xE, _, 0xA, 0x1) => SkipKeyNotPressed(x),
+        (0xF, 0x0, 0x0, 0x0) => LoadLongIndex,
+        (0xF, _, 0x0, 0x1) => SelectPlanes(x),
+        (0xF, 0x0, 0x0, 0x2) => LoadAudioPattern,
+        (0xF, _, 0x0, 0x7) => GetDelay(x),
+        (0xF, _, 0x0, 0xA) => WaitKey(x),
+        (0xF, _, 0x1, 0x5) => SetDelay(x),
+        (0xF, _, 0x1, 0x8) => SetSound(x),
+        (0xF, _, 0x1, 0xE) => AddIndex(x),
+        (0xF, _, 0x2, 0x9) => LoadFont(x),
+        (0xF, _, 0x3, 0x0) => LoadBigFont(x),
+        (0xF, _, 0x3, 0x3) => StoreBcd(x),
+        (0xF, _, 0x3, 0xA) => SetPitch(x),
+        (0xF, _, 0x5, 0x5) => Store(x),
+        (0xF, _, 0x6, 0x5) => Load(x),
+        (0xF, _, 0x7, 0x5) => SaveFlags(x),
+        (0xF, _, 0x8, 0x5) => LoadFlags(x),
+        _ => return None,
+    };
+
+    Some(instruction)
+}
+
+/// Decode an opcode as `instruction_set` runs it. Instructions of a later machine
+/// are invalid, except in the 0NNN range where they are machine code calls
+pub fn decode_for(opcode: u16, instruction_set: InstructionSet) -> Option<Instruction> {
+    match decode(opcode)? {
+        instruction if instruction.instruction_set() <= instruction_set => Some(instruction),
+        _ if opcode >> 12 == 0 => Some(Instruction::Sys(opcode & 0xfff)),
+        _ => None,
+    }
+}
+
+impl Instruction {
+
+    /// The first machine to support this instruction
+    pub fn instruction_set(&self) -> InstructionSet {
+        use Instruction::*;
+
+        match self {
+            ScrollDown(_) | ScrollRight | ScrollLeft | Exit | Lores | Hires
+            | LoadBigFont(_) | SaveFlags(_) | LoadFlags(_) => InstructionSet::SuperChip,
+            ScrollUp(_) | SaveRange(..) | LoadRange(..) | LoadLongIndex
+            | SelectPlanes(_) | LoadAudioPattern | SetPitch(_) => InstructionSet::XoChip,
+            _ => InstructionSet::Chip8,
+        }
+    }
+
+    /// Encode the instruction into the opcode `decode` turns back into it. For
+    /// F000 NNNN only the first word is returned, the address has to follow it
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |x: u8, y: u8| (x as u16 & 0xf) << 8 | (y as u16 & 0xf) << 4;
+        let xkk = |x: u8, kk: u8| (x as u16 & 0xf) << 8 | kk as u16;
+        let x_ = |x: u8| (x as u16 & 0xf) << 8;
+
+        match *self {
+            Sys(nnn) => nnn & 0xfff,
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xf),
+            ScrollUp(n) => 0x00D0 | (n as u16 & 0xf),
+            Clear => 0x00E0,
+            Return => 0x00EE,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            Jump(nnn) => 0x1000 | (nnn & 0xfff),
+            Call(nnn) => 0x2000 | (nnn & 0xfff),
+            SkipEqualByte(x, kk) => 0x3000 | xkk(x, kk),
+            SkipNotEqualByte(x, kk) => 0x4000 | xkk(x, kk),
+            SkipEqual(x, y) => 0x5000 | xy(x, y),
+            SaveRange(x, y) => 0x5002 | xy(x, y),
+            LoadRange(x, y) => 0x5003 | xy(x, y),
+            LoadByte(x, kk) => 0x6000 | xkk(x, kk),
+            AddByte(x, kk) => 0x7000 | xkk(x, kk),
+            Move(x, y) => 0x8000 | xy(x, y),
+            Or(x, y) => 0x8001 | xy(x, y),
+            And(x, y) => 0x8002 | xy(x, y),
+            Xor(x, y) => 0x8003 | xy(x, y),
+            Add(x, y) => 0x8004 | xy(x, y),
+            Sub(x, y) => 0x8005 | xy(x, y),
+            ShiftRight(x, y) => 0x8006 | xy(x, y),
+            SubReverse(x, y) => 0x8007 | xy(x, y),
+            ShiftLeft(x, y) => 0x800E | xy(x, y),
+            SkipNotEqual(x, y) => 0x9000 | xy(x, y),
+            LoadIndex(nnn) => 0xA000 | (nnn & 0xfff),
+            JumpOffset(nnn) => 0xB000 | (nnn & 0xfff),
+            Random(x, kk) => 0xC000 | xkk(x, kk),
+            Draw(x, y, n) => 0xD000 | xy(x, y) | (n as u16 & 0xf),
+            SkipKeyPressed(x) => 0xE09E | x_(x),
+            SkipKeyNotPressed(x) => 0xE0A1 | x_(x),
+            LoadLongIndex => 0xF000,
+            SelectPlanes(n) => 0xF001 | x_(n),
+            LoadAudioPattern => 0xF002,
+            GetDelay(x) => 0xF007 | x_(x),
+            WaitKey(x) => 0xF00A | x_(x),
+            SetDelay(x) => 0xF015 | x_(x),
+            SetSound(x) => 0xF018 | x_(x),
+            AddIndex(x) => 0xF01E | x_(x),
+            LoadFont(x) => 0xF029 | x_(x),
+            LoadBigFont(x) => 0xF030 | x_(x),
+            StoreBcd(x) => 0xF033 | x_(x),
+            SetPitch(x) => 0xF03A | x_(x),
+            Store(x) => 0xF055 | x_(x),
+            Load(x) => 0xF065 | x_(x),
+            SaveFlags(x) => 0xF075 | x_(x),
+            LoadFlags(x) => 0xF085 | x_(x),
+        }
+    }
+
+    /// Size in bytes including any operand, F000 NNNN is the only 4 byte instruction
+    pub fn size(&self) -> u16 {
+        if *self == Instruction::LoadLongIndex { 4 } else { 2 }
+    }
+
+    /// Whether the instruction may skip over the next one
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(self,
+            SkipEqualByte(..) | SkipNotEqualByte(..) | SkipEqual(..) | SkipNotEqual(..)
+            | SkipKeyPressed(_) | SkipKeyNotPressed(_))
+    }
+
+    /// The 12 bit address operand of a SYS, JP, CALL, LD I or JP V0 instruction
+    pub fn address(&self) -> Option<u16> {
+        use Instruction::*;
+
+        match *self {
+            Sys(nnn) | Jump(nnn) | Call(nnn) | LoadIndex(nnn) | JumpOffset(nnn) => Some(nnn),
+            _ => None,
+        }
+    }
+
+    /// Print the instruction in the given syntax. F000 NNNN is printed without
+    /// its address, which is expected to follow
+    pub fn format(&self, syntax: Syntax) -> String {
+        match syntax {
+            Syntax::Cowgod => self.format_cowgod(),
+            Syntax::Octo => self.format_octo(),
+        }
+    }
+
+    fn format_cowgod(&self) -> String {
+        use Instruction::*;
+
+        match *self {
+            Sys(nnn) => format!("SYS {:#05x}", nnn),
+            ScrollDown(n) => format!("SCD {}", n),
+            ScrollUp(n) => format!("SCU {}", n),
+            Clear => "CLS".to_string(),
+            Return => "RET".to_string(),
+            ScrollRight => "SCR".to_string(),
+            ScrollLeft => "SCL".to_string(),
+            Exit => "EXIT".to_string(),
+            Lores => "LOW".to_string(),
+            Hires => "HIGH".to_string(),
+            Jump(nnn) => format!("JP {:#05x}", nnn),
+            Call(nnn) => format!("CALL {:#05x}", nnn),
+            SkipEqualByte(x, kk) => format!("SE V{:X}, {:#04x}", x, kk),
+            SkipNotEqualByte(x, kk) => format!("SNE V{:X}, {:#04x}", x, kk),
+            SkipEqual(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            SaveRange(x, y) => format!("SAVE V{:X} - V{:X}", x, y),
+            LoadRange(x, y) => format!("LOAD V{:X} - V{:X}", x, y),
+            LoadByte(x, kk) => format!("LD V{:X}, {:#04x}", x, kk),
+            AddByte(x, kk) => format!("ADD V{:X}, {:#04x}", x, kk),
+            Move(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Add(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            SubReverse(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            SkipNotEqual(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            LoadIndex(nnn) => format!("LD I, {:#05x}", nnn),
+            JumpOffset(nnn) => format!("JP V0, {:#05x}", nnn),
+            Random(x, kk) => format!("RND V{:X}, {:#04x}", x, kk),
+            Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKeyPressed(x) => format!("SKP V{:X}", x),
+            SkipKeyNotPressed(x) => format!("SKNP V{:X}", x),
+            LoadLongIndex => "LD I, LONG".to_string(),
+            SelectPlanes(n) => format!("PLANE {}", n),
+            LoadAudioPattern => "AUDIO".to_string(),
+            GetDelay(x) => format!("LD V{:X}, DT", x),
+            WaitKey(x) => format!("LD V{:X}, K", x),
+            SetDelay(x) => format!("LD DT, V{:X}", x),
+            SetSound(x) => format!("LD ST, V{:X}", x),
+            AddIndex(x) => format!("ADD I, V{:X}", x),
+            LoadFont(x) => format!("LD F, V{:X}", x),
+            LoadBigFont(x) => format!("LD HF, V{:X}", x),
+            StoreBcd(x) => format!("LD B, V{:X}", x),
+            SetPitch(x) => format!("LD PITCH, V{:X}", x),
+            Store(x) => format!("LD [I], V{:X}", x),
+            Load(x) => format!("LD V{:X}, [I]", x),
+            SaveFlags(x) => format!("LD R, V{:X}", x),
+            LoadFlags(x) => format!("LD V{:X}, R", x),
+        }
+    }
+
+    fn format_octo(&self) -> String {
+        use Instruction::*;
+
+        match *self {
+            // Octo has no mnemonic for machine code calls, emit the raw bytes
+            Sys(nnn) => format!("{:#04x} {:#04x}", nnn >> 8, nnn & 0xff),
+            ScrollDown(n) => format!("scroll-down {}", n),
+            ScrollUp(n) => format!("scroll-up {}", n),
+            Clear => "clear".to_string(),
+            Return => "return".to_string(),
+            ScrollRight => "scroll-right".to_string(),
+            ScrollLeft => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            Lores => "lores".to_string(),
+            Hires => "hires".to_string(),
+            Jump(nnn) => format!("jump {:#05x}", nnn),
+            Call(nnn) => format!(":call {:#05x}", nnn),
+            // Octo conditions say when the next instruction runs, the opposite of the skip
+            SkipEqualByte(x, kk) => format!("if v{:x} != {:#04x} then", x, kk),
+            SkipNotEqualByte(x, kk) => format!("if v{:x} == {:#04x} then", x, kk),
+            SkipEqual(x, y) => format!("if v{:x} != v{:x} then", x, y),
+            SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+            LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+            LoadByte(x, kk) => format!("v{:x} := {:#04x}", x, kk),
+            AddByte(x, kk) => format!("v{:x} += {:#04x}", x, kk),
+            Move(x, y) => format!("v{:x} := v{:x}", x, y),
+            Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+            And(x, y) => format!("v{:x} &= v{:x}", x, y),
+            Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+            Add(x, y) => format!("v{:x} += v{:x}", x, y),
+            Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+            ShiftRight(x, y) => format!("v{:x} >>= v{:x}", x, y),
+            SubReverse(x, y) => format!("v{:x} =- v{:x}", x, y),
+            ShiftLeft(x, y) => format!("v{:x} <<= v{:x}", x, y),
+            SkipNotEqual(x, y) => format!("if v{:x} == v{:x} then", x, y),
+            LoadIndex(nnn) => format!("i := {:#05x}", nnn),
+            JumpOffset(nnn) => format!("jump0 {:#05x}", nnn),
+            Random(x, kk) => format!("v{:x} := random {:#04x}", x, kk),
+            Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            SkipKeyPressed(x) => format!("if v{:x} -key then", x),
+            SkipKeyNotPressed(x) => format!("if v{:x} key then", x),
+            LoadLongIndex => "i := long".to_string(),
+            SelectPlanes(n) => format!("plane {}", n),
+            LoadAudioPattern => "audio".to_string(),
+            GetDelay(x) => format!("v{:x} := delay", x),
+            WaitKey(x) => format!("v{:x} := key", x),
+            SetDelay(x) => format!("delay := v{:x}", x),
+            SetSound(x) => format!("buzzer := v{:x}", x),
+            AddIndex(x) => format!("i += v{:x}", x),
+            LoadFont(x) => format!("i := hex v{:x}", x),
+            LoadBigFont(x) => format!("i := bighex v{:x}", x),
+            StoreBcd(x) => format!("bcd v{:x}", x),
+            SetPitch(x) => format!("pitch := v{:x}", x),
+            Store(x) => format!("save v{:x}", x),
+            Load(x) => format!("load v{:x}", x),
+            SaveFlags(x) => format!("saveflags v{:x}", x),
+            LoadFlags(x) => format!("loadflags v{:x}", x),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(Syntax::Cowgod))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_decode() {
+        assert_eq!(decode(0x00E0), Some(Instruction::Clear));
+        assert_eq!(decode(0x0123), Some(Instruction::Sys(0x123)));
+        assert_eq!(decode(0x1ABC), Some(Instruction::Jump(0xABC)));
+        assert_eq!(decode(0x8A4E), Some(Instruction::ShiftLeft(0xA, 0x4)));
+        assert_eq!(decode(0xD12F), Some(Instruction::Draw(0x1, 0x2, 0xF)));
+        assert_eq!(decode(0xF000), Some(Instruction::LoadLongIndex));
+        assert_eq!(decode(0xF301), Some(Instruction::SelectPlanes(3)));
+        assert_eq!(decode(0x5121), None);
+        assert_eq!(decode(0x8128), None);
+        assert_eq!(decode(0x9121), None);
+        assert_eq!(decode(0xE19F), None);
+        assert_eq!(decode(0xF1FF), None);
+        assert_eq!(decode(0xF100), None);
+    }
+
+    #[test]
+    pub fn test_encode_round_trip() {
+        for opcode in 0..=0xffff {
+            if let Some(instruction) = decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_decode_for_instruction_set() {
+        assert_eq!(decode_for(0x00FF, InstructionSet::Chip8), Some(Instruction::Sys(0x0FF)));
+        assert_eq!(decode_for(0x00FF, InstructionSet::SuperChip), Some(Instruction::Hires));
+        assert_eq!(decode_for(0x00D1, InstructionSet::SuperChip), Some(Instruction::Sys(0x0D1)));
+        assert_eq!(decode_for(0x00D1, InstructionSet::XoChip), Some(Instruction::ScrollUp(1)));
+        assert_eq!(decode_for(0xF030, InstructionSet::Chip8), None);
+        assert_eq!(decode_for(0x5122, InstructionSet::SuperChip), None);
+        assert_eq!(decode_for(0xF000, InstructionSet::XoChip), Some(Instruction::LoadLongIndex));
+    }
+
+    #[test]
+    pub fn test_format() {
+        let instruction = decode(0x6A2B).unwrap();
+        assert_eq!(instruction.format(Syntax::Cowgod), "LD VA, 0x2b");
+        assert_eq!(instruction.format(Syntax::Octo), "va := 0x2b");
+
+        assert_eq!(decode(0x3105).unwrap().format(Syntax::Octo), "if v1 != 0x05 then");
+        assert_eq!(decode(0x2208).unwrap().to_string(), "CALL 0x208");
+        assert_eq!(decode(0xF265).unwrap().format(Syntax::Octo), "load v2");
+    }
+}
@@ -1,31 +1,91 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
 pub struct Keyboard {
-    key_pressed: Option<u8>
+    /// Bit n is set while key n is held down
+    keys_down: u16
 }
 
 impl Keyboard {
 
     /// Creates a new Keyboard instance
     pub fn new() -> Self {
-        Keyboard{ key_pressed: None }
+        Keyboard{ keys_down: 0 }
     }
 
     /// Returns whether or not the key_code passed is in being pressed
     pub fn is_key_pressed(&self, key_code: u8) -> bool {
-        if let Some(key) = self.key_pressed {
-            key == key_code
+        self.keys_down & Keyboard::mask_for(key_code) != 0
+    }
+
+    /// Mark a key as held down
+    pub fn key_down(&mut self, key_code: u8) {
+        self.keys_down |= Keyboard::mask_for(key_code);
+    }
+
+    /// Mark a key as released
+    pub fn key_up(&mut self, key_code: u8) {
+        self.keys_down &= !Keyboard::mask_for(key_code);
+    }
+
+    /// Get the lowest key being pressed
+    pub fn get_key_pressed(&self) -> Option<u8> {
+        if self.keys_down == 0 {
+            None
         }
         else {
-            false
+            Some(self.keys_down.trailing_zeros() as u8)
         }
     }
 
-    /// Set the key being pressed
-    pub fn set_key_pressed(&mut self, key: Option<u8>) {
-        self.key_pressed = key;
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.keys_down);
     }
 
-    /// Get the key being pressed
-    pub fn get_key_pressed(&self) -> Option<u8> {
-        self.key_pressed
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.keys_down = reader.read_u16()?;
+        Ok(())
+    }
+
+    /// Only the low nibble selects a key, as on the COSMAC VIP
+    fn mask_for(key_code: u8) -> u16 {
+        1 << (key_code & 0xf)
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_multiple_keys_down() {
+        let mut keyboard = Keyboard::new();
+        keyboard.key_down(0x1);
+        keyboard.key_down(0xC);
+
+        assert!(keyboard.is_key_pressed(0x1));
+        assert!(keyboard.is_key_pressed(0xC));
+        assert!(!keyboard.is_key_pressed(0x2));
+        assert_eq!(keyboard.get_key_pressed(), Some(0x1));
+    }
+
+    #[test]
+    pub fn test_key_up() {
+        let mut keyboard = Keyboard::new();
+        keyboard.key_down(0x1);
+        keyboard.key_down(0xC);
+        keyboard.key_up(0x1);
+
+        assert!(!keyboard.is_key_pressed(0x1));
+        assert!(keyboard.is_key_pressed(0xC));
+        assert_eq!(keyboard.get_key_pressed(), Some(0xC));
+
+        keyboard.key_up(0xC);
+        assert_eq!(keyboard.get_key_pressed(), None);
     }
-}
\ No newline at end of file
+}
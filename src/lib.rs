@@ -0,0 +1,66 @@
+//! A CHIP-8, SUPER-CHIP and XO-CHIP emulator core with no window or audio
+//! dependencies. The `frontend` feature adds a window and sound to the `chip8`
+//! binary, while `chip8-headless` and `chip8 run --headless` run ROMs without a
+//! window for automated tests.
+//!
+//! ```
+//! use chip8::{Chip8, Quirks};
+//!
+//! // V0 = 0x2A, loop forever
+//! let mut chip8 = Chip8::builder()
+//!     .quirks(Quirks::cosmac_vip())
+//!     .rom(&[0x60, 0x2A, 0x12, 0x02])
+//!     .build();
+//!
+//! chip8.run_frame(8).unwrap();
+//! assert_eq!(chip8.cpu.read_reg(0), 0x2A);
+//! ```
+
+// Disable source code being included in rustdoc
+#![doc(html_no_source)]
+
+extern crate rand;
+
+pub mod assembler;
+pub mod audio;
+pub mod bus;
+pub mod chip8;
+pub mod cli;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod gdbstub;
+pub mod headless;
+pub mod instruction;
+pub mod keyboard;
+pub mod memory;
+pub mod overlay;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod scheduler;
+pub mod state;
+pub mod timing;
+pub mod trace;
+pub mod watchpoint;
+
+pub use crate::assembler::{assemble, assemble_file, load_rom, AssembleError, Program};
+pub use crate::audio::{AudioPattern, AudioSink, NullSink, RecordingSink};
+pub use crate::bus::Bus;
+pub use crate::chip8::{Chip8, Chip8Builder};
+pub use crate::cpu::{Cpu, CpuError, StepOutcome};
+pub use crate::debugger::{Debugger, StopReason};
+pub use crate::display::Display;
+pub use crate::gdbstub::GdbStub;
+pub use crate::headless::{HeadlessRunner, KeyScript, RunLimit, RunSummary};
+pub use crate::instruction::{decode, Instruction, Syntax};
+pub use crate::keyboard::Keyboard;
+pub use crate::memory::Memory;
+pub use crate::quirks::{IndexIncrement, InstructionSet, Quirks};
+pub use crate::rewind::Rewind;
+pub use crate::rng::Xorshift;
+pub use crate::scheduler::{Scheduler, Tick};
+pub use crate::state::StateError;
+pub use crate::trace::Tracer;
+pub use crate::watchpoint::{WatchHit, WatchKind, Watchpoint};
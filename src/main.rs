@@ -1,135 +1,136 @@
 // Disable source code being included in rustdoc
 #![doc(html_no_source)]
 
+extern crate chip8;
+#[cfg(feature = "frontend")]
+extern crate cpal;
+#[cfg(feature = "frontend")]
 extern crate minifb;
-extern crate rand;
 
-use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use chip8::assembler::{assemble_file, load_rom, Program};
+use chip8::cli::{self, AsmArgs, Command, DisasmArgs, InfoArgs, RunArgs, USAGE};
+use chip8::disassembler::{disassemble, LineKind};
+use chip8::instruction::decode_for;
+use chip8::{Instruction, InstructionSet};
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::time::{Duration, Instant};
-
-mod bus;
-mod chip8;
-mod cpu;
-mod display;
-mod keyboard;
-mod memory;
-
-fn get_chip8_keycode_for(key: Option<Key>) -> Option<u8> {
-    match key {
-        // Row 0
-        Some(Key::Key1) => Some(0x1),
-        Some(Key::Key2) => Some(0x2),
-        Some(Key::Key3) => Some(0x3),
-        Some(Key::Key4) => Some(0xC),
-        // Row 1
-        Some(Key::Q) => Some(0x4),
-        Some(Key::W) => Some(0x5),
-        Some(Key::E) => Some(0x6),
-        Some(Key::R) => Some(0xD),
-        // Row 2
-        Some(Key::A) => Some(0x7),
-        Some(Key::S) => Some(0x8),
-        Some(Key::D) => Some(0x9),
-        Some(Key::F) => Some(0xE),
-        // Row 3
-        Some(Key::Z) => Some(0xA),
-        Some(Key::X) => Some(0x0),
-        Some(Key::C) => Some(0xB),
-        Some(Key::V) => Some(0xF),
-        // Catch all
-        _ => None
-
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+#[cfg(feature = "frontend")]
+mod speaker;
+#[cfg(feature = "frontend")]
+mod window;
+
+/// `chip8 disasm` prints a listing of the ROM
+fn disasm(args: DisasmArgs) -> Result<(), Box<dyn Error>> {
+    let data = fs::read(&args.rom_path).map_err(|e| format!("unable to read {}: {}", args.rom_path, e))?;
+    for line in disassemble(&data, args.quirks.instruction_set) {
+        println!("{}", line.format(args.syntax));
     }
+    Ok(())
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file = File::open(&args[1]);
-    let mut data = Vec::<u8>::new();
+/// `chip8 asm` assembles Octo source. The ROM is written next to the source
+/// unless `-o` is given, `--run` runs it straight away and only writes it when
+/// `-o` is also given
+fn asm(args: AsmArgs) -> Result<(), Box<dyn Error>> {
+    let program = assemble_file(Path::new(&args.source_path))?;
 
-    // Make sure we can read the ROM
-    if let Ok(mut v) = file {
-        match v.read_to_end(&mut data) {
-            Err(e) => panic!(e),
-            _ => ()
-        };
+    let mut output_path = args.output_path;
+    if output_path.is_none() && !args.run {
+        output_path = Some(Path::new(&args.source_path).with_extension("ch8").display().to_string());
     }
-    else {
-        panic!("Unable to read input")
+    if let Some(output_path) = &output_path {
+        fs::write(output_path, &program.rom).map_err(|e| format!("unable to write {}: {}", output_path, e))?;
+    }
+    if args.run {
+        let mut run_args = RunArgs::new(output_path.as_deref().unwrap_or(&args.source_path));
+        run_args.quirks = args.quirks;
+        run_window(&program, &run_args)?;
     }
+    Ok(())
+}
 
-    let width = 640;
-    let height = 320;
+/// The oldest machine which understands `instruction`
+fn required_instruction_set(instruction: Instruction) -> InstructionSet {
+    [InstructionSet::Chip8, InstructionSet::SuperChip, InstructionSet::XoChip].iter()
+        .copied()
+        .find(|set| decode_for(instruction.encode(), *set) == Some(instruction))
+        .unwrap_or(InstructionSet::XoChip)
+}
 
-    let mut buffer: Vec<u32> = vec![0; width * height];
+/// `chip8 info` describes a ROM: its size, the machine its code needs and, for
+/// Octo source, its labels and breakpoints
+fn info(args: InfoArgs) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(&args.rom_path);
+    let program = load_rom(path).map_err(|e| format!("unable to load {}: {}", args.rom_path, e))?;
+    let instructions: Vec<Instruction> = disassemble(&program.rom, InstructionSet::XoChip).iter()
+        .filter_map(|line| match line.kind {
+            LineKind::Code(instruction) => Some(instruction),
+            LineKind::Data => None,
+        })
+        .collect();
+    let code_size: usize = instructions.iter().map(|instruction| instruction.size() as usize).sum();
+    let (machine, preset) = match instructions.iter().map(|instruction| required_instruction_set(*instruction)).max() {
+        Some(InstructionSet::XoChip) => ("XO-CHIP", "xochip"),
+        Some(InstructionSet::SuperChip) => ("SUPER-CHIP", "schip"),
+        _ => ("CHIP-8", "vip"),
+    };
+
+    println!("File:        {}", args.rom_path);
+    println!("Size:        {} bytes", program.rom.len());
+    println!("Machine:     {}, run with --quirks {}", machine, preset);
+    println!("Code:        {} instructions reachable from the start, {} bytes", instructions.len(), code_size);
+    println!("Data:        {} bytes", program.rom.len() - code_size);
+    if path.extension().map_or(false, |extension| extension == "8o") {
+        println!("Labels:      {}", program.labels.len());
+        println!("Breakpoints: {}", program.breakpoints.len());
+    }
+    Ok(())
+}
 
-    let mut window = Window::new(
-        "Chip8 Emulator",
-        width,
-        height,
-        WindowOptions::default()
-    ).unwrap_or_else(|e| {
-        panic!("Window creation failed: {:?}", e);
+fn main() {
+    let command = cli::parse(env::args().skip(1).collect()).unwrap_or_else(|e| {
+        eprintln!("chip8: {}", e);
+        eprintln!("Run `chip8 help` for usage");
+        process::exit(2);
     });
 
-    let mut chip8 = chip8::Chip8::new();
-    chip8.load_rom(&data);
-
-    let mut last_key_update_time = Instant::now();
-    let mut last_instruction_run_time = Instant::now();
-    let mut last_display_time = Instant::now();
-
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        let keys_pressed = window.get_keys_pressed(KeyRepeat::Yes);
-        let key = match keys_pressed {
-            Some(keys) => if !keys.is_empty() {
-                Some(keys[0])
-            }
-            else {
-                None
-            },
-            None => None,
-        };
-
-        let chip8_key = get_chip8_keycode_for(key);
-
-        if chip8_key.is_some() || Instant::now() - last_key_update_time >= Duration::from_millis(200) {
-            last_key_update_time = Instant::now();
-            chip8.set_key_pressed(chip8_key);
-        }
-
-        if Instant::now() - last_instruction_run_time > Duration::from_millis(2) {
-            chip8.run_instruction();
-            last_instruction_run_time = Instant::now();
+    let result = match command {
+        Command::Help => {
+            print!("{}", USAGE);
+            Ok(())
         }
+        Command::Run(args) => run(&args),
+        Command::Disasm(args) => disasm(args),
+        Command::Asm(args) => asm(args),
+        Command::Info(args) => info(args),
+    };
+    if let Err(e) = result {
+        eprintln!("chip8: {}", e);
+        process::exit(1);
+    }
+}
 
-        if Instant::now() - last_display_time > Duration::from_millis(10) {
-            let chip8_buffer = chip8.get_display_buffer();
-
-            for y in 0..height {
-                let y_pos = y / 10;
-                let offset = y * width;
-                for x in 0..width {
-                    let index = display::Display::get_index_from_coords(x / 10, y_pos);
-                    let pixel = chip8_buffer[index];
-                    let color_pixel = match pixel {
-                        0 => 0x0,
-                        1 => 0xffffff,
-                        _ => unreachable!(),
-                    };
-                    buffer[offset + x] = color_pixel;
-                }
-            }
-            match window.update_with_buffer(&buffer, width, height) {
-                Ok(_) => {},
-                Err(e) => panic!("Error updating window: {:#?}", e)
-            }
-
-            last_display_time = Instant::now();
-        }
+/// `chip8 run` loads the ROM, assembling Octo source first, and runs it in a window or headless
+fn run(args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    let program = cli::load_program(args)?;
+    if args.headless {
+        cli::run_headless(&program, args)
+    }
+    else {
+        run_window(&program, args)
     }
+}
+
+#[cfg(feature = "frontend")]
+fn run_window(program: &Program, args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    window::run_window(program, args)
+}
 
+#[cfg(not(feature = "frontend"))]
+fn run_window(_program: &Program, _args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    Err("built without the frontend feature, only --headless runs are available".into())
 }
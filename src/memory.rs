@@ -1,43 +1,60 @@
-/// CHIP-8 Memory Layout ([credits](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#memmap))
-/// ```text
-/// +---------------+ = 0xFFF (4095) End of Chip-8 RAM
-/// |               |
-/// |               |
-/// |               |
-/// |               |
-/// |               |
-/// | 0x200 to 0xFFF|
-/// |     Chip-8    |
-/// | Program / Data|
-/// |     Space     |
-/// |               |
-/// |               |
-/// |               |
-/// +- - - - - - - -+ = 0x600 (1536) Start of ETI 660 Chip-8 programs
-/// |               |
-/// |               |
-/// |               |
-/// +---------------+ = 0x200 (512) Start of most Chip-8 programs
-/// | 0x000 to 0x1FF|
-/// | Reserved for  |
-/// |  interpreter  |
-/// +---------------+ = 0x000 (0) Start of Chip-8 RAM
-/// ```
+//! CHIP-8 Memory Layout ([credits](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#memmap))
+//! ```text
+//! +---------------+ = 0xFFF (4095) End of Chip-8 RAM
+//! |               |
+//! |               |
+//! |               |
+//! |               |
+//! |               |
+//! | 0x200 to 0xFFF|
+//! |     Chip-8    |
+//! | Program / Data|
+//! |     Space     |
+//! |               |
+//! |               |
+//! |               |
+//! +- - - - - - - -+ = 0x600 (1536) Start of ETI 660 Chip-8 programs
+//! |               |
+//! |               |
+//! |               |
+//! +---------------+ = 0x200 (512) Start of most Chip-8 programs
+//! | 0x000 to 0x1FF|
+//! | Reserved for  |
+//! |  interpreter  |
+//! +---------------+ = 0x000 (0) Start of Chip-8 RAM
+//! ```
+
+use crate::state::{StateError, StateReader, StateWriter};
 
 pub const SIZE: usize = 4096; 
 
+/// XO-CHIP extends the address space to the full 16 bits
+pub const XO_CHIP_SIZE: usize = 0x10000;
+
+/// Address of the 5 byte hex digit sprites
+pub const FONT_ADDRESS: u16 = 0x000;
+
+/// Address of the 10 byte SUPER-CHIP hex digit sprites, right after the small font
+pub const BIG_FONT_ADDRESS: u16 = 0x050;
+
 pub struct Memory {
-    ram: [u8; SIZE],
+    ram: Vec<u8>,
 }
 
 impl Memory {
 
     /// Creates a new instance of Memory and initializes the values
     pub fn new() -> Self {
-        
+        Memory::with_size(SIZE)
+    }
+
+    /// Creates a new instance of Memory of `size` bytes, which must be a power of two
+    pub fn with_size(size: usize) -> Self {
+        assert!(size.is_power_of_two() && size <= XO_CHIP_SIZE, "Invalid memory size {}", size);
+
         // Create a new instance of Self
         let mut memory = Memory {
-            ram: [0; 4096],
+            ram: vec![0; size],
 
         };
 
@@ -61,8 +78,28 @@ impl Memory {
             [0xf0, 0x80, 0xf0, 0x80, 0x80]  // "F"
         ];
 
+        // Create the SUPER-CHIP sprites
+        let big_sprites: [[u8; 10]; 16] = [
+            [0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff], // "0"
+            [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xff, 0xff], // "1"
+            [0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff], // "2"
+            [0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff], // "3"
+            [0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0x03, 0x03], // "4"
+            [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff], // "5"
+            [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff], // "6"
+            [0xff, 0xff, 0x03, 0x03, 0x06, 0x0c, 0x18, 0x18, 0x18, 0x18], // "7"
+            [0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff], // "8"
+            [0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff], // "9"
+            [0x7e, 0xff, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3], // "A"
+            [0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc], // "B"
+            [0x3c, 0xff, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0xff, 0x3c], // "C"
+            [0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc], // "D"
+            [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff], // "E"
+            [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0]  // "F"
+        ];
+
         // Load the sprites into memory
-        let mut i: u16 = 0;
+        let mut i: u16 = FONT_ADDRESS;
         for sprite in &sprites {
             for element in sprite {
                 memory.write_byte(i, *element);
@@ -70,20 +107,58 @@ impl Memory {
             }
         }
 
+        let mut i: u16 = BIG_FONT_ADDRESS;
+        for sprite in &big_sprites {
+            for element in sprite {
+                memory.write_byte(i, *element);
+                i += 1;
+            }
+        }
+
         memory
     }
 
 
-    /// Read a byte from memory given the address. While the address is a u16,
-    /// we mask it with 0xfff since the address space max is 0xfff
+    /// Get the size of memory in bytes
+    pub fn get_size(&self) -> usize {
+        self.ram.len()
+    }
+
+    /// The address an access to `address` lands on. While the address is a u16,
+    /// we mask it with the size of memory so accesses wrap around, e.g. 0xfff on CHIP-8
+    pub fn wrap_address(&self, address: u16) -> u16 {
+        (address as usize & (self.ram.len() - 1)) as u16
+    }
+
+    /// Read a byte from memory given the address, wrapping around the end of memory
     pub fn read_byte(&self, address: u16) -> u8 {
-        self.ram[(address & 0xfff) as usize]
+        self.ram[self.wrap_address(address) as usize]
     }
 
-    /// Write a byte to memory given the address. While the address is a u16,
-    /// we mask it with 0xfff since the address space max is 0xfff
+    /// Write a byte to memory given the address, wrapping around the end of memory
     pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.ram[(address & 0xfff) as usize] = value;
+        let address = self.wrap_address(address);
+        self.ram[address as usize] = value;
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.ram.len() as u32);
+        writer.write_bytes(&self.ram);
     }
 
-}
\ No newline at end of file
+    /// The savestate must have been taken with the same amount of memory
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let size = reader.read_u32()? as usize;
+        if size != self.ram.len() {
+            return Err(StateError::MemorySizeMismatch { expected: self.ram.len(), found: size });
+        }
+        reader.read_into(&mut self.ram)
+    }
+
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new()
+    }
+}
@@ -0,0 +1,122 @@
+//! Drawing short status text, like the speed indicator, over a frontend's pixel buffer
+
+/// Glyphs are 3 pixels wide and 5 high, each row a 3 bit mask with the leftmost pixel highest
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+/// Blank pixels between glyphs and around the text
+const SPACING: usize = 1;
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        // Anything else is drawn as a space
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Size in buffer pixels of `text` drawn at `scale`, including its background margin
+pub fn text_size(text: &str, scale: usize) -> (usize, usize) {
+    let glyphs = text.chars().count();
+    let width = SPACING + glyphs * (GLYPH_WIDTH + SPACING);
+    (width * scale, (GLYPH_HEIGHT + 2 * SPACING) * scale)
+}
+
+/// Draw `text` in `colour` on a `background` box in the top left corner of a
+/// buffer `width` pixels wide. Anything outside the buffer is clipped
+pub fn draw_text(buffer: &mut [u32], width: usize, text: &str, scale: usize, colour: u32, background: u32) {
+    let height = buffer.len() / width;
+    let (box_width, box_height) = text_size(text, scale);
+    let mut plot = |px: usize, py: usize, value: u32| {
+        if px < width && py < height {
+            buffer[py * width + px] = value;
+        }
+    };
+
+    for py in 0..box_height {
+        for px in 0..box_width {
+            plot(px, py, background);
+        }
+    }
+    for (index, c) in text.chars().enumerate() {
+        let left = (SPACING + index * (GLYPH_WIDTH + SPACING)) * scale;
+        let top = SPACING * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        plot(left + column * scale + dx, top + row * scale + dy, colour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(buffer: &[u32], width: usize) -> Vec<String> {
+        buffer.chunks(width)
+            .map(|row| row.iter().map(|pixel| if *pixel == 1 { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    pub fn test_draw_text() {
+        assert_eq!(text_size("I1", 1), (9, 7));
+        let mut buffer = vec![9; 10 * 7];
+        draw_text(&mut buffer, 10, "I1", 1, 1, 0);
+
+        let expected = [
+            "..........",
+            ".###..#...",
+            "..#..##...",
+            "..#...#...",
+            "..#...#...",
+            ".###.###..",
+            "..........",
+        ];
+        assert_eq!(render(&buffer, 10), expected);
+        // Outside the box is untouched
+        assert_eq!(buffer[9], 9);
+    }
+
+    #[test]
+    pub fn test_draw_text_clips() {
+        let mut buffer = vec![0; 4 * 4];
+        draw_text(&mut buffer, 4, "100%", 2, 1, 0);
+        assert_eq!(buffer.len(), 16);
+        // Only the margin and the blank left column of the 1 fit
+        assert_eq!(&buffer[0..4], &[0, 0, 0, 0]);
+        assert_eq!(&buffer[8..12], &[0, 0, 0, 0]);
+    }
+}
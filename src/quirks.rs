@@ -0,0 +1,117 @@
+/// How FX55 and FX65 leave the I register once they are done
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// I is left untouched (SUPER-CHIP 1.1)
+    Unchanged,
+    /// I is incremented by X (CHIP-48)
+    ByX,
+    /// I is incremented by X + 1 (COSMAC VIP, XO-CHIP)
+    ByXPlusOne,
+}
+
+/// The set of instructions understood on top of the original CHIP-8 ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InstructionSet {
+    /// The original COSMAC VIP instructions
+    Chip8,
+    /// Adds the 128x64 high resolution mode, scrolling, 16x16 sprites,
+    /// the big font, exit and the RPL user flags
+    SuperChip,
+    /// Adds 64 KiB of memory, a second bitplane, audio patterns and the
+    /// register range load and store instructions on top of SUPER-CHIP
+    XoChip,
+}
+
+/// The interpretation to use for the opcodes whose behaviour differs
+/// between CHIP-8 implementations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    /// What happens to I after FX55 and FX65
+    pub load_store: IndexIncrement,
+    /// BXNN jumps to XNN + Vx instead of NNN + V0
+    pub jump_uses_vx: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping
+    pub clip_sprites: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// DXYN waits for the next frame before drawing, limiting draws to 60 a second
+    pub display_wait: bool,
+    /// Which extensions to the instruction set are available
+    pub instruction_set: InstructionSet,
+}
+
+impl Quirks {
+
+    /// The original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            display_wait: true,
+            instruction_set: InstructionSet::Chip8,
+        }
+    }
+
+    /// CHIP-48 for the HP-48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            instruction_set: InstructionSet::Chip8,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub fn superchip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            instruction_set: InstructionSet::SuperChip,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+            instruction_set: InstructionSet::XoChip,
+        }
+    }
+
+    /// Look up a preset by name: `vip`, `chip48`, `schip` or `xochip`
+    pub fn from_preset_name(name: &str) -> Option<Self> {
+        match name {
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::superchip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None
+        }
+    }
+}
+
+impl Default for Quirks {
+
+    /// Defaults to the COSMAC VIP behaviour
+    fn default() -> Self {
+        Quirks::cosmac_vip()
+    }
+}
@@ -0,0 +1,210 @@
+use crate::chip8::Chip8;
+use std::collections::VecDeque;
+
+/// Longest run of unchanged or changed bytes stored in one delta chunk
+const MAX_RUN: usize = u16::MAX as usize;
+
+/// Ring buffer of the most recent frames, for stepping gameplay backwards.
+/// Only the newest savestate is kept whole, every older frame is stored as the
+/// difference to the frame after it, which is a few bytes for most frames
+pub struct Rewind {
+    /// Deltas from each frame to the one before it, newest last
+    deltas: VecDeque<Vec<u8>>,
+    /// Savestate of the newest frame
+    current: Option<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Rewind {
+
+    /// Create a buffer able to step back `capacity` frames
+    pub fn new(capacity: usize) -> Self {
+        Rewind { deltas: VecDeque::with_capacity(capacity), current: None, capacity }
+    }
+
+    /// Record the machine as it is now, call once per frame. The oldest frame is dropped when full
+    pub fn push(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+        if let Some(current) = self.current.take() {
+            // Every savestate of a machine has the same layout, anything else can't be diffed
+            if current.len() == state.len() {
+                if self.deltas.len() == self.capacity {
+                    self.deltas.pop_front();
+                }
+                if self.capacity > 0 {
+                    self.deltas.push_back(encode_delta(&state, &current));
+                }
+            }
+            else {
+                self.deltas.clear();
+            }
+        }
+        self.current = Some(state);
+    }
+
+    /// Step the machine back to the previous recorded frame, returns false once the buffer is exhausted
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> bool {
+        match (self.deltas.pop_back(), self.current.as_mut()) {
+            (Some(delta), Some(current)) => {
+                apply_delta(current, &delta);
+                chip8.load_state(current).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// Number of frames that can be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Forget every recorded frame, e.g. when switching to another ROM
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.current = None;
+    }
+}
+
+/// XOR `from` and `to` then run length encode the result as chunks of
+/// `[unchanged count: u16][changed count: u16][changed bytes]`
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut index = 0;
+
+    loop {
+        let mut unchanged = from[index..].iter().zip(&to[index..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        index += unchanged;
+        if index == from.len() {
+            break;
+        }
+        // Long stretches of unchanged bytes, e.g. untouched memory, span several empty chunks
+        while unchanged > MAX_RUN {
+            delta.extend_from_slice(&(MAX_RUN as u16).to_be_bytes());
+            delta.extend_from_slice(&[0, 0]);
+            unchanged -= MAX_RUN;
+        }
+        let changed = from[index..].iter().zip(&to[index..])
+            .take(MAX_RUN)
+            .take_while(|(a, b)| a != b)
+            .count();
+
+        delta.extend_from_slice(&(unchanged as u16).to_be_bytes());
+        delta.extend_from_slice(&(changed as u16).to_be_bytes());
+        for (a, b) in from[index..index + changed].iter().zip(&to[index..index + changed]) {
+            delta.push(a ^ b);
+        }
+        index += changed;
+    }
+
+    delta
+}
+
+/// Turn the `from` state given to `encode_delta` into the `to` state
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut index = 0;
+    let mut chunks = delta;
+
+    while chunks.len() >= 4 {
+        let unchanged = u16::from_be_bytes([chunks[0], chunks[1]]) as usize;
+        let changed = u16::from_be_bytes([chunks[2], chunks[3]]) as usize;
+        index += unchanged;
+        for (byte, xor) in state[index..index + changed].iter_mut().zip(&chunks[4..4 + changed]) {
+            *byte ^= xor;
+        }
+        index += changed;
+        chunks = &chunks[4 + changed..];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    /// V0 += 1, draw the font sprite for V0 at (V0, V0), loop
+    const COUNTER_ROM: [u8; 8] = [0x70, 0x01, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+
+    #[test]
+    pub fn test_delta_round_trip() {
+        let from: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let mut to = from.clone();
+        to[0] ^= 0xff;
+        to[70_000] = 0;
+        for byte in to[100_000..170_000].iter_mut() {
+            *byte = byte.wrapping_add(1);
+        }
+
+        let mut state = from.clone();
+        apply_delta(&mut state, &encode_delta(&from, &to));
+        assert_eq!(state, to);
+
+        assert!(encode_delta(&from, &from).is_empty());
+    }
+
+    #[test]
+    pub fn test_rewind_steps_back_frames() {
+        let mut chip8 = Chip8::builder().rom(&COUNTER_ROM).build();
+        let mut rewind = Rewind::new(60);
+        let mut states = Vec::new();
+
+        for _ in 0..10 {
+            chip8.run_frame(4).unwrap();
+            rewind.push(&chip8);
+            states.push(chip8.save_state());
+        }
+        assert_eq!(rewind.len(), 9);
+
+        for expected in states.iter().rev().skip(1) {
+            assert!(rewind.rewind(&mut chip8));
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(!rewind.rewind(&mut chip8));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    pub fn test_rewind_then_continue() {
+        let mut chip8 = Chip8::builder().rom(&COUNTER_ROM).build();
+        let mut rewind = Rewind::new(60);
+        for _ in 0..5 {
+            chip8.run_frame(4).unwrap();
+            rewind.push(&chip8);
+        }
+        let frame_two = {
+            assert!(rewind.rewind(&mut chip8));
+            assert!(rewind.rewind(&mut chip8));
+            chip8.save_state()
+        };
+
+        chip8.run_frame(4).unwrap();
+        rewind.push(&chip8);
+        assert!(rewind.rewind(&mut chip8));
+        assert_eq!(chip8.save_state(), frame_two);
+    }
+
+    #[test]
+    pub fn test_capacity_drops_oldest() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&COUNTER_ROM);
+        let mut rewind = Rewind::new(3);
+
+        for _ in 0..10 {
+            chip8.run_frame(4).unwrap();
+            rewind.push(&chip8);
+        }
+        assert_eq!(rewind.len(), 3);
+
+        for _ in 0..3 {
+            assert!(rewind.rewind(&mut chip8));
+        }
+        assert!(!rewind.rewind(&mut chip8));
+        // Back at the seventh frame, four instructions per frame
+        assert_eq!(chip8.cpu.read_reg(0), 7);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Small xorshift64* generator behind CXKK. Unlike the OS seeded generators in
+/// `rand` its state is a single word, so runs can be reproduced from a seed and
+/// the generator can be captured in a savestate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+
+    /// Create a generator which always produces the same sequence for the same seed
+    pub fn new(seed: u64) -> Self {
+        // Spread the seed with a splitmix64 step so small seeds don't start with a run of zeros
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        // The all zero state would only ever produce zeros
+        Xorshift { state: if z == 0 { 0x9e37_79b9_7f4a_7c15 } else { z } }
+    }
+
+    /// Create a generator with a random seed
+    pub fn from_entropy() -> Self {
+        Xorshift::new(rand::random())
+    }
+
+    /// Get the next value, every byte from 0x00 to 0xFF is equally likely
+    pub fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        // The high bits of the multiplied state are the best distributed
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.state);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let state = reader.read_u64()?;
+        if state == 0 {
+            return Err(StateError::InvalidValue("random number generator"));
+        }
+        self.state = state;
+        Ok(())
+    }
+}
+
+impl Default for Xorshift {
+    fn default() -> Self {
+        Xorshift::from_entropy()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_same_seed_same_sequence() {
+        let mut a = Xorshift::new(1234);
+        let mut b = Xorshift::new(1234);
+        let mut c = Xorshift::new(1235);
+
+        let sequence: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        assert_eq!(sequence, (0..32).map(|_| b.next_byte()).collect::<Vec<u8>>());
+        assert_ne!(sequence, (0..32).map(|_| c.next_byte()).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    pub fn test_full_byte_range() {
+        let mut rng = Xorshift::new(0);
+        let mut seen = [false; 256];
+        for _ in 0..10_000 {
+            seen[rng.next_byte() as usize] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+}
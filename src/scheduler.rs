@@ -0,0 +1,236 @@
+//! Deciding how much to run on each tick of a 60 Hz frontend: pausing, stepping
+//! a frame or an instruction at a time, fast forwarding, and measuring the speed
+//! actually achieved
+
+use std::time::{Duration, Instant};
+
+/// The slowest speed which can be set, one instruction per frame
+pub const MIN_INSTRUCTIONS_PER_FRAME: usize = 1;
+
+/// The fastest speed which can be set
+pub const MAX_INSTRUCTIONS_PER_FRAME: usize = 10_000;
+
+/// Frames run for every host frame while fast forwarding
+pub const TURBO_FRAMES: usize = 5;
+
+/// How long the effective speed is averaged over
+const SPEED_WINDOW: Duration = Duration::from_secs(1);
+
+/// Emulated frames per second at full speed
+const FRAMES_PER_SECOND: f64 = 60.0;
+
+/// What to run on one tick of the frontend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tick {
+    /// Run this many frames, none while paused
+    Frames(usize),
+    /// Run a single instruction without finishing the frame
+    Instruction,
+}
+
+/// Speed and pause state for the frontend, which calls `tick` once per 60 Hz frame
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    instructions_per_frame: usize,
+    paused: bool,
+    turbo: bool,
+    /// The machine runs by cycles, making the instructions per frame meaningless
+    vip_timing: bool,
+    advance: Option<Tick>,
+    window_start: Option<Instant>,
+    window_frames: usize,
+    /// Emulated frames per second over the last window, relative to 60
+    speed: f64,
+}
+
+impl Scheduler {
+
+    pub fn new(instructions_per_frame: usize) -> Self {
+        Scheduler {
+            instructions_per_frame: instructions_per_frame.clamp(MIN_INSTRUCTIONS_PER_FRAME, MAX_INSTRUCTIONS_PER_FRAME),
+            paused: false,
+            turbo: false,
+            vip_timing: false,
+            advance: None,
+            window_start: None,
+            window_frames: 0,
+            speed: 1.0,
+        }
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    /// Change the instructions per frame by `delta`, staying within the supported range
+    pub fn adjust_instructions_per_frame(&mut self, delta: isize) {
+        let ipf = self.instructions_per_frame as isize + delta;
+        self.instructions_per_frame = ipf.clamp(MIN_INSTRUCTIONS_PER_FRAME as isize, MAX_INSTRUCTIONS_PER_FRAME as isize) as usize;
+    }
+
+    /// Show the speed as VIP timing rather than instructions per frame
+    pub fn set_vip_timing(&mut self, enabled: bool) {
+        self.vip_timing = enabled;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance = None;
+    }
+
+    /// Run one more frame on the next tick, pausing if running
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.advance = Some(Tick::Frames(1));
+    }
+
+    /// Run one more instruction on the next tick, pausing if running
+    pub fn advance_instruction(&mut self) {
+        self.paused = true;
+        self.advance = Some(Tick::Instruction);
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    /// Fast forward for as long as the turbo key is held
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    /// What to run for the host frame starting at `now`
+    pub fn tick(&mut self, now: Instant) -> Tick {
+        let tick = if self.paused {
+            self.advance.take().unwrap_or(Tick::Frames(0))
+        }
+        else if self.turbo {
+            Tick::Frames(TURBO_FRAMES)
+        }
+        else {
+            Tick::Frames(1)
+        };
+
+        // Frames are counted in the window they start in
+        let window_start = *self.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(window_start);
+        if elapsed >= SPEED_WINDOW {
+            self.speed = self.window_frames as f64 / elapsed.as_secs_f64() / FRAMES_PER_SECOND;
+            self.window_start = Some(now);
+            self.window_frames = 0;
+        }
+        if let Tick::Frames(frames) = tick {
+            self.window_frames += frames;
+        }
+        tick
+    }
+
+    /// Emulated speed over the last second, 1.0 being full speed
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// A short description for an on-screen indicator, e.g. `IPF 8 100%`
+    pub fn status(&self) -> String {
+        let state = if self.paused {
+            "PAUSED "
+        }
+        else if self.turbo {
+            "TURBO "
+        }
+        else {
+            ""
+        };
+        if self.vip_timing {
+            format!("{}VIP {:.0}%", state, self.speed * 100.0)
+        }
+        else {
+            format!("{}IPF {} {:.0}%", state, self.instructions_per_frame, self.speed * 100.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ticks at exactly 60 Hz, returning the last tick
+    fn run_ticks(scheduler: &mut Scheduler, start: Instant, ticks: u32) -> Tick {
+        let frame = Duration::from_micros(16_667);
+        (0..ticks).map(|tick| scheduler.tick(start + frame * tick)).last().unwrap()
+    }
+
+    #[test]
+    pub fn test_pause_and_advance() {
+        let mut scheduler = Scheduler::new(8);
+        let now = Instant::now();
+        assert_eq!(scheduler.tick(now), Tick::Frames(1));
+
+        scheduler.toggle_pause();
+        assert_eq!(scheduler.tick(now), Tick::Frames(0));
+
+        scheduler.advance_frame();
+        assert_eq!(scheduler.tick(now), Tick::Frames(1));
+        assert_eq!(scheduler.tick(now), Tick::Frames(0));
+
+        scheduler.advance_instruction();
+        assert_eq!(scheduler.tick(now), Tick::Instruction);
+        assert_eq!(scheduler.tick(now), Tick::Frames(0));
+
+        scheduler.toggle_pause();
+        assert_eq!(scheduler.tick(now), Tick::Frames(1));
+    }
+
+    #[test]
+    pub fn test_advance_pauses() {
+        let mut scheduler = Scheduler::new(8);
+        scheduler.advance_frame();
+        assert!(scheduler.is_paused());
+        assert_eq!(scheduler.tick(Instant::now()), Tick::Frames(1));
+        assert_eq!(scheduler.tick(Instant::now()), Tick::Frames(0));
+    }
+
+    #[test]
+    pub fn test_turbo() {
+        let mut scheduler = Scheduler::new(8);
+        scheduler.set_turbo(true);
+        assert_eq!(scheduler.tick(Instant::now()), Tick::Frames(TURBO_FRAMES));
+
+        // Pausing wins over fast forwarding
+        scheduler.toggle_pause();
+        assert_eq!(scheduler.tick(Instant::now()), Tick::Frames(0));
+    }
+
+    #[test]
+    pub fn test_adjust_instructions_per_frame() {
+        let mut scheduler = Scheduler::new(8);
+        scheduler.adjust_instructions_per_frame(4);
+        assert_eq!(scheduler.instructions_per_frame(), 12);
+        scheduler.adjust_instructions_per_frame(-100);
+        assert_eq!(scheduler.instructions_per_frame(), MIN_INSTRUCTIONS_PER_FRAME);
+        assert_eq!(Scheduler::new(0).instructions_per_frame(), MIN_INSTRUCTIONS_PER_FRAME);
+    }
+
+    #[test]
+    pub fn test_speed() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(8);
+        run_ticks(&mut scheduler, start, 62);
+        assert_eq!(scheduler.status(), "IPF 8 100%");
+        scheduler.set_vip_timing(true);
+        assert_eq!(scheduler.status(), "VIP 100%");
+
+        let mut scheduler = Scheduler::new(8);
+        scheduler.set_turbo(true);
+        run_ticks(&mut scheduler, start, 62);
+        assert_eq!(scheduler.status(), "TURBO IPF 8 500%");
+
+        scheduler.toggle_pause();
+        run_ticks(&mut scheduler, start + Duration::from_secs(2), 62);
+        assert_eq!(scheduler.status(), "PAUSED IPF 8 0%");
+    }
+}
@@ -0,0 +1,84 @@
+use chip8::audio::{AudioPattern, AudioSink, PatternWave, SquareWave, BEEP_FREQUENCY};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// State shared with the audio callback
+#[derive(Default)]
+struct SpeakerState {
+    beeping: AtomicBool,
+    /// A new XO-CHIP pattern waiting to be picked up by the callback
+    pending_pattern: Mutex<Option<AudioPattern>>,
+}
+
+/// Plays the beeper on the default output device
+pub struct Speaker {
+    state: Arc<SpeakerState>,
+    // The stream stops playing once dropped
+    _stream: cpal::Stream,
+}
+
+impl Speaker {
+
+    /// Open the default output device, returns None if there is no usable device
+    pub fn new() -> Option<Self> {
+        let device = cpal::default_host().default_output_device()?;
+        let supported_config = device.default_output_config().ok()?;
+        let sample_format = supported_config.sample_format();
+        let config: cpal::StreamConfig = supported_config.into();
+        let state = Arc::new(SpeakerState::default());
+
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => Speaker::build_stream::<f32>(&device, &config, state.clone()),
+            cpal::SampleFormat::I16 => Speaker::build_stream::<i16>(&device, &config, state.clone()),
+            cpal::SampleFormat::U16 => Speaker::build_stream::<u16>(&device, &config, state.clone()),
+        }?;
+        stream.play().ok()?;
+
+        Some(Speaker { state, _stream: stream })
+    }
+
+    fn build_stream<T: cpal::Sample>(device: &cpal::Device, config: &cpal::StreamConfig, state: Arc<SpeakerState>) -> Option<cpal::Stream> {
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+        let mut wave = SquareWave::new(BEEP_FREQUENCY, sample_rate);
+        let mut pattern_wave: Option<PatternWave> = None;
+
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                // Never block the audio thread, a new pattern can wait for the next callback
+                if let Ok(mut pending) = state.pending_pattern.try_lock() {
+                    if let Some(pattern) = pending.take() {
+                        pattern_wave = Some(PatternWave::new(pattern, sample_rate));
+                    }
+                }
+
+                let on = state.beeping.load(Ordering::Relaxed);
+                for frame in data.chunks_mut(channels) {
+                    let sample = match (on, pattern_wave.as_mut()) {
+                        (false, _) => 0.0,
+                        (true, Some(pattern_wave)) => pattern_wave.next_sample(),
+                        (true, None) => wave.next_sample(),
+                    };
+                    for channel in frame.iter_mut() {
+                        *channel = cpal::Sample::from(&sample);
+                    }
+                }
+            },
+            |e| eprintln!("Audio stream error: {}", e)
+        ).ok()
+    }
+}
+
+impl AudioSink for Speaker {
+    fn set_beeping(&mut self, beeping: bool) {
+        self.state.beeping.store(beeping, Ordering::Relaxed);
+    }
+
+    fn set_pattern(&mut self, pattern: AudioPattern) {
+        if let Ok(mut pending) = self.state.pending_pattern.lock() {
+            *pending = Some(pattern);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::error::Error;
+use std::fmt;
+
+/// Identifies a savestate, followed by the format version
+pub const MAGIC: &[u8; 4] = b"C8ST";
+
+/// Bumped whenever the layout of a savestate changes
+pub const VERSION: u8 = 3;
+
+/// Errors when restoring a savestate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the savestate magic
+    BadMagic,
+    /// The savestate was written by an incompatible version
+    UnsupportedVersion(u8),
+    /// The data ended before the savestate was complete
+    Truncated,
+    /// The savestate was taken on a machine with a different amount of memory
+    MemorySizeMismatch { expected: usize, found: usize },
+    /// A field holds a value the machine can never be in
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a savestate"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported savestate version {}", version),
+            StateError::Truncated => write!(f, "savestate is truncated"),
+            StateError::MemorySizeMismatch { expected, found } => {
+                write!(f, "savestate has {} bytes of memory, expected {}", found, expected)
+            }
+            StateError::InvalidValue(field) => write!(f, "savestate has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Appends the fields of a savestate, multi-byte values are big endian
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+
+    /// Creates a writer with the magic and version already written
+    pub fn new() -> Self {
+        let mut writer = StateWriter::default();
+        writer.write_bytes(MAGIC);
+        writer.write_u8(VERSION);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Get the finished savestate
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads back the fields written by a StateWriter
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+
+    /// Creates a reader after checking the magic and version
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut reader = StateReader { data };
+        if reader.read_bytes(MAGIC.len()).map_err(|_| StateError::BadMagic)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidValue("flag")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        self.read_into(&mut bytes)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Read exactly `count` bytes
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < count {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Fill `buffer` with the next bytes
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        Ok(())
+    }
+
+    /// Check that the whole savestate was consumed
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(StateError::InvalidValue("length"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789abcde);
+        writer.write_u64(0x0123456789abcdef);
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u32(), Ok(0x789abcde));
+        assert_eq!(reader.read_u64(), Ok(0x0123456789abcdef));
+        let mut buffer = [0; 3];
+        reader.read_into(&mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3]);
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    pub fn test_bad_header() {
+        assert_eq!(StateReader::new(b"C8").err(), Some(StateError::BadMagic));
+        assert_eq!(StateReader::new(b"NOPE\x01").err(), Some(StateError::BadMagic));
+        assert_eq!(StateReader::new(b"C8ST\x63").err(), Some(StateError::UnsupportedVersion(0x63)));
+    }
+
+    #[test]
+    pub fn test_truncated() {
+        let mut reader = StateReader::new(b"C8ST\x03\x12").unwrap();
+        assert_eq!(reader.read_u16(), Err(StateError::Truncated));
+    }
+}
@@ -0,0 +1,87 @@
+//! Instruction costs of the COSMAC VIP interpreter, in 1802 machine cycles
+//!
+//! The VIP clocks its 1802 at 1.76 MHz and every machine cycle takes 8 clocks,
+//! giving 3668 machine cycles per 60 Hz frame. The 1861 display steals 1024 of
+//! them for DMA and its interrupt routine takes a few more, the rest are left
+//! for the interpreter. The costs are approximations of the interpreter's own
+//! routines, close enough for games to run at the pace they were tuned for.
+
+use crate::instruction::Instruction;
+
+/// Machine cycles in one 60 Hz frame
+pub const CYCLES_PER_FRAME: u32 = 3668;
+
+/// Machine cycles taken each frame by the display DMA and the interrupt routine
+pub const DISPLAY_CYCLES_PER_FRAME: u32 = 1024 + 29;
+
+/// Machine cycles left for running instructions in each frame
+pub const INTERPRETER_CYCLES_PER_FRAME: u32 = CYCLES_PER_FRAME - DISPLAY_CYCLES_PER_FRAME;
+
+/// Fetching and dispatching an opcode, paid by every instruction
+const FETCH_CYCLES: u32 = 40;
+
+/// Extra cycles for stepping over the next instruction when a skip is taken
+const SKIP_CYCLES: u32 = 4;
+
+/// Machine cycles taken by an instruction. `vx` is the value its X register
+/// held before it ran and `skipped` whether it skipped the next instruction.
+/// Doesn't include the wait for the vertical blank before a sprite is drawn
+pub fn instruction_cycles(instruction: Instruction, vx: u8, skipped: bool) -> u32 {
+    use Instruction::*;
+
+    let execute = match instruction {
+        Clear => 24 + 3 * 256,
+        Return => 10,
+        Sys(_) | Jump(_) => 12,
+        Call(_) => 26,
+        JumpOffset(_) => 22,
+        SkipEqualByte(..) | SkipNotEqualByte(..) => 10,
+        SkipEqual(..) | SkipNotEqual(..) | SkipKeyPressed(_) | SkipKeyNotPressed(_) => 14,
+        LoadByte(..) => 6,
+        AddByte(..) => 10,
+        // The VIP runs these through a small routine it builds in RAM
+        Move(..) | Or(..) | And(..) | Xor(..) | Add(..) | Sub(..) | ShiftRight(..) | SubReverse(..) | ShiftLeft(..) => 44,
+        LoadIndex(_) => 12,
+        Random(..) => 36,
+        Draw(_, _, n) => draw_cycles(vx, n),
+        GetDelay(_) | WaitKey(_) | SetDelay(_) | SetSound(_) => 10,
+        AddIndex(_) => 16,
+        LoadFont(_) => 20,
+        // Each digit is found by repeated subtraction
+        StoreBcd(_) => 84 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u32,
+        Store(x) | Load(x) => 14 + 14 * (x as u32 + 1),
+        // Not run by the VIP, charged like the register arithmetic
+        _ => 44,
+    };
+    FETCH_CYCLES + execute + if skipped { SKIP_CYCLES } else { 0 }
+}
+
+/// A sprite row not aligned to a byte is shifted across two bytes of the display
+fn draw_cycles(x: u8, rows: u8) -> u32 {
+    let row = if x % 8 == 0 { 34 } else { 46 };
+    26 + row * rows as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_instruction_cycles() {
+        assert_eq!(instruction_cycles(Instruction::LoadByte(0, 1), 0, false), 46);
+        assert_eq!(instruction_cycles(Instruction::SkipEqualByte(0, 1), 0, false), 50);
+        assert_eq!(instruction_cycles(Instruction::SkipEqualByte(0, 1), 1, true), 54);
+        assert_eq!(instruction_cycles(Instruction::Store(2), 0, false), 96);
+        assert_eq!(instruction_cycles(Instruction::StoreBcd(0), 0, false), 124);
+        assert_eq!(instruction_cycles(Instruction::StoreBcd(0), 255, false), 124 + 16 * 12);
+    }
+
+    #[test]
+    pub fn test_draw_cycles_depend_on_alignment() {
+        let aligned = instruction_cycles(Instruction::Draw(0, 1, 5), 8, false);
+        let unaligned = instruction_cycles(Instruction::Draw(0, 1, 5), 9, false);
+        assert_eq!(aligned, 40 + 26 + 34 * 5);
+        assert_eq!(unaligned, 40 + 26 + 46 * 5);
+        assert!(instruction_cycles(Instruction::Draw(0, 1, 15), 8, false) > aligned);
+    }
+}
@@ -0,0 +1,142 @@
+//! Logging every executed instruction, for diffing runs against other emulators
+//!
+//! Each line shows the machine before the instruction runs, e.g.
+//!
+//! ```text
+//! 00000000 PC:0200 OP:00E0 CLS                  V:00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I:0000 SP:0 DT:00 ST:00
+//! ```
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::instruction::{decode_for, Syntax};
+use std::io;
+use std::io::Write;
+
+/// Width of the disassembly column, so the registers line up
+const DISASSEMBLY_WIDTH: usize = 20;
+
+/// Writes one trace line per instruction to any writer
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    cycle: u64,
+    /// Tracing stops at the first write error, which is kept for `finish`
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Tracer { writer, cycle: 0, error: None }
+    }
+
+    /// Log the instruction about to run at the program counter
+    pub(crate) fn trace(&mut self, cpu: &Cpu, bus: &Bus) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = writeln!(self.writer, "{}", trace_line(self.cycle, cpu, bus)) {
+            self.error = Some(e);
+        }
+        self.cycle += 1;
+    }
+
+    /// Flush the log, returning the first error hit while writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+/// Format the state of the machine before the instruction at the program counter runs
+pub fn trace_line(cycle: u64, cpu: &Cpu, bus: &Bus) -> String {
+    let pc = cpu.get_pc();
+    let opcode = (bus.memory_peek_byte(pc) as u16) << 8 | bus.memory_peek_byte(pc.wrapping_add(1)) as u16;
+    let disassembly = match decode_for(opcode, cpu.get_quirks().instruction_set) {
+        Some(instruction) => instruction.format(Syntax::Cowgod),
+        None => "???".to_string(),
+    };
+    let registers: Vec<String> = cpu.get_registers().iter().map(|value| format!("{:02X}", value)).collect();
+
+    format!(
+        "{:08} PC:{:04X} OP:{:04X} {:<width$} V:{} I:{:04X} SP:{:X} DT:{:02X} ST:{:02X}",
+        cycle,
+        pc,
+        opcode,
+        disassembly,
+        registers.join(" "),
+        cpu.get_i(),
+        cpu.get_sp(),
+        bus.get_delay_timer(),
+        bus.get_sound_timer(),
+        width = DISASSEMBLY_WIDTH,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::cpu::DRAW_TWICE_PROGRAM;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A writer whose contents can still be read after it is given to a Tracer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_trace_line() {
+        let mut chip8 = Chip8::builder().rom(&[0x60, 0x2A, 0xA3, 0x00]).build();
+        chip8.run_instruction().unwrap();
+
+        assert_eq!(
+            trace_line(1, &chip8.cpu, &chip8.bus),
+            "00000001 PC:0202 OP:A300 LD I, 0x300          V:2A 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I:0000 SP:0 DT:00 ST:00"
+        );
+    }
+
+    #[test]
+    pub fn test_traces_every_instruction() {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = Chip8::builder().rom(&[0x60, 0x2A, 0x12, 0x02]).build();
+        chip8.set_tracer(Tracer::new(Box::new(buffer.clone())));
+
+        chip8.run_frame(3).unwrap();
+        chip8.take_tracer().unwrap().finish().unwrap();
+        chip8.run_instruction().unwrap();
+
+        let log = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00000000 PC:0200 OP:602A LD V0, 0x2a"));
+        assert!(lines[2].starts_with("00000002 PC:0202 OP:1202 JP 0x202"));
+        assert!(lines[2].contains("V:2A 00"));
+    }
+
+    #[test]
+    pub fn test_waiting_draw_is_traced_once() {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = Chip8::builder().rom(&DRAW_TWICE_PROGRAM).build();
+        chip8.set_tracer(Tracer::new(Box::new(buffer.clone())));
+
+        chip8.run_frame(8).unwrap();
+        chip8.run_frame(8).unwrap();
+        chip8.take_tracer().unwrap().finish().unwrap();
+
+        let log = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let pcs: Vec<&str> = log.lines().map(|line| &line[9..16]).collect();
+        assert_eq!(pcs, ["PC:0200", "PC:0202", "PC:0204"]);
+    }
+}
@@ -0,0 +1,105 @@
+//! Watchpoints checked by the `Bus` on every memory access made by an instruction
+
+use std::fmt;
+
+/// What kind of access a watchpoint catches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Any read, except fetching instructions
+    Read,
+    /// Any write, even one storing the value already there
+    Write,
+    /// A write storing a different value
+    Change,
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+        })
+    }
+}
+
+/// Watches the addresses `start` through `end`, inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+
+    pub fn new(start: u16, end: u16, kind: WatchKind) -> Self {
+        Watchpoint { start, end, kind }
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+
+    /// Whether a read, or a write of `old` replaced by `new`, triggers this watchpoint
+    pub(crate) fn matches(&self, address: u16, access: Option<(u8, u8)>) -> bool {
+        self.contains(address) && match (self.kind, access) {
+            (WatchKind::Read, None) => true,
+            (WatchKind::Write, Some(_)) => true,
+            (WatchKind::Change, Some((old, new))) => old != new,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{} {:#05x}", self.kind, self.start)
+        }
+        else {
+            write!(f, "{} {:#05x}-{:#05x}", self.kind, self.start, self.end)
+        }
+    }
+}
+
+/// The first access which triggered a watchpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub kind: WatchKind,
+    pub address: u16,
+    /// The value read, or the value before a write
+    pub old: u8,
+    /// The value written, the same as `old` for reads
+    pub new: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            WatchKind::Read => write!(f, "read {:#04x} from {:#05x}", self.old, self.address),
+            _ => write!(f, "wrote {:#04x} to {:#05x}, was {:#04x}", self.new, self.address, self.old),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_matches() {
+        let read = Watchpoint::new(0x300, 0x302, WatchKind::Read);
+        assert!(read.matches(0x301, None));
+        assert!(!read.matches(0x303, None));
+        assert!(!read.matches(0x301, Some((1, 2))));
+
+        let write = Watchpoint::new(0x300, 0x300, WatchKind::Write);
+        assert!(write.matches(0x300, Some((1, 1))));
+        assert!(!write.matches(0x300, None));
+
+        let change = Watchpoint::new(0x300, 0x300, WatchKind::Change);
+        assert!(change.matches(0x300, Some((1, 2))));
+        assert!(!change.matches(0x300, Some((1, 1))));
+    }
+}
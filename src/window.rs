@@ -0,0 +1,350 @@
+//! The window, keyboard and sound of the `chip8` binary
+
+use crate::speaker;
+use chip8::assembler::Program;
+use chip8::cli::{build_chip8, finish_trace, RunArgs};
+use chip8::display;
+use chip8::overlay::draw_text;
+use chip8::{Chip8, CpuError, Debugger, GdbStub, Rewind, Scheduler, StopReason, Tick};
+use minifb::{Key, KeyRepeat, ScaleMode, Window, WindowOptions};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Length of one emulated 60 Hz frame
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+/// Most frames run back to back to catch up when the main loop falls behind
+const MAX_CATCH_UP_FRAMES: u32 = 6;
+
+/// Host keys for the CHIP-8 hex keypad, laid out as
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D  ->  Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+const KEYMAP: [(Key, u8); 16] = [
+    // Row 0
+    (Key::Key1, 0x1),
+    (Key::Key2, 0x2),
+    (Key::Key3, 0x3),
+    (Key::Key4, 0xC),
+    // Row 1
+    (Key::Q, 0x4),
+    (Key::W, 0x5),
+    (Key::E, 0x6),
+    (Key::R, 0xD),
+    // Row 2
+    (Key::A, 0x7),
+    (Key::S, 0x8),
+    (Key::D, 0x9),
+    (Key::F, 0xE),
+    // Row 3
+    (Key::Z, 0xA),
+    (Key::X, 0x0),
+    (Key::C, 0xB),
+    (Key::V, 0xF),
+];
+
+/// Hold to play the last few seconds backwards
+const REWIND_KEY: Key = Key::Backspace;
+
+/// Number of frames kept for rewinding, 10 seconds
+const REWIND_FRAMES: usize = 600;
+
+/// Pause and resume
+const PAUSE_KEY: Key = Key::P;
+
+/// Run one frame, pausing first if running
+const FRAME_ADVANCE_KEY: Key = Key::N;
+
+/// Run one instruction, pausing first if running
+const INSTRUCTION_ADVANCE_KEY: Key = Key::I;
+
+/// Hold to fast forward
+const TURBO_KEY: Key = Key::Tab;
+
+/// Run fewer or more instructions per frame
+const SLOWER_KEY: Key = Key::Minus;
+const FASTER_KEY: Key = Key::Equal;
+
+/// Show or hide the speed indicator in the top left corner
+const STATUS_KEY: Key = Key::F12;
+
+/// The speed indicator's pixels are this many times smaller than low resolution pixels
+const STATUS_SCALE_DIVISOR: usize = 5;
+
+/// F1 to F10 load a savestate slot, holding shift saves to it instead
+const STATE_SLOT_KEYS: [Key; 10] = [
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5,
+    Key::F6, Key::F7, Key::F8, Key::F9, Key::F10,
+];
+
+/// Savestates are kept next to the ROM, e.g. `pong.ch8.state1`
+fn state_slot_path(rom_path: &str, slot: usize) -> String {
+    format!("{}.state{}", rom_path, slot + 1)
+}
+
+/// Save to or load from a slot, reporting the result in the window title. Returns whether it succeeded
+fn handle_state_slot(chip8: &mut Chip8, window: &mut Window, rom_path: &str, slot: usize, save: bool) -> bool {
+    let path = state_slot_path(rom_path, slot);
+    let result = if save {
+        fs::write(&path, chip8.save_state()).map_err(|e| e.to_string())
+    }
+    else {
+        fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| chip8.load_state(&data).map_err(|e| e.to_string()))
+    };
+
+    let action = if save { "saved" } else { "loaded" };
+    match result {
+        Ok(()) => {
+            window.set_title(&format!("Chip8 Emulator - {} slot {}", action, slot + 1));
+            true
+        }
+        Err(e) => {
+            eprintln!("Unable to use savestate {}: {}", path, e);
+            window.set_title(&format!("Chip8 Emulator - slot {} not {}: {}", slot + 1, action, e));
+            false
+        }
+    }
+}
+
+/// Run up to `frames` frames, recording each for rewinding. Stops early when the
+/// debugger stops or the program exits
+fn run_frames(debugger: &mut Debugger, chip8: &mut Chip8, rewind: &mut Rewind, frames: usize, instructions_per_frame: usize) -> Result<Option<StopReason>, CpuError> {
+    for _ in 0..frames {
+        let was_paused = debugger.is_paused();
+        let stop = debugger.run_frame(chip8, instructions_per_frame)?;
+        if !was_paused {
+            rewind.push(chip8);
+        }
+        if stop.is_some() || chip8.has_exited() {
+            return Ok(stop);
+        }
+    }
+    Ok(None)
+}
+
+/// Read debugger commands on another thread so the window keeps updating while waiting for input
+fn spawn_prompt() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    print_prompt();
+    receiver
+}
+
+fn print_prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+/// Run a program in a window until it is closed or the program exits. Fails if
+/// the program is still crashed when the window is closed
+pub fn run_window(program: &Program, args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    let rom_path = args.rom_path.as_str();
+    let mut chip8 = build_chip8(program, args)?;
+    if !args.mute {
+        match speaker::Speaker::new() {
+            Some(speaker) => chip8.set_audio_sink(Box::new(speaker)),
+            None => eprintln!("No audio output device found, running without sound"),
+        }
+    }
+
+    let width = display::WIDTH * args.scale;
+    let height = display::HEIGHT * args.scale;
+    let mut buffer: Vec<u32> = vec![0; width * height];
+    // minifb has no exclusive fullscreen, the closest is a borderless window which can be stretched
+    let window_options = if args.fullscreen {
+        WindowOptions { borderless: true, topmost: true, resize: true, scale_mode: ScaleMode::AspectRatioStretch, ..WindowOptions::default() }
+    }
+    else {
+        WindowOptions::default()
+    };
+    let mut window = Window::new("Chip8 Emulator", width, height, window_options)
+        .map_err(|e| format!("unable to create the window: {}", e))?;
+
+    let mut rewind = Rewind::new(REWIND_FRAMES);
+    let mut crashed = None;
+    let mut debugger = Debugger::new();
+    let commands = if args.debug {
+        debugger.set_labels(program.labels.clone());
+        for address in program.breakpoints.keys() {
+            debugger.add_breakpoint(*address);
+        }
+        debugger.pause();
+        println!("Paused at {}, type help for commands", debugger.describe_pc(&chip8));
+        Some(spawn_prompt())
+    } else {
+        None
+    };
+    let mut gdb = match args.gdb_port {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("unable to listen on port {}: {}", port, e))?;
+            println!("Waiting for GDB on 127.0.0.1:{}", port);
+            Some(GdbStub::accept(&listener, args.instructions_per_frame).map_err(|e| format!("GDB failed to connect: {}", e))?)
+        }
+        None => None,
+    };
+    // Started after waiting for GDB, so the wait isn't run as frames owed
+    let mut last_frame_time = Instant::now();
+    let mut last_display_time = Instant::now();
+    let mut waiting_for_key = false;
+    let mut scheduler = Scheduler::new(args.instructions_per_frame);
+    scheduler.set_vip_timing(chip8.has_vip_timing());
+    let mut show_status = true;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        for (key, chip8_key) in KEYMAP.iter() {
+            if window.is_key_down(*key) {
+                chip8.key_down(*chip8_key);
+            }
+            else {
+                chip8.key_up(*chip8_key);
+            }
+        }
+
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        for (slot, key) in STATE_SLOT_KEYS.iter().enumerate() {
+            if window.is_key_pressed(*key, KeyRepeat::No)
+                && handle_state_slot(&mut chip8, &mut window, rom_path, slot, shift) {
+                // Loading a state is a way out of a crash
+                if !shift {
+                    crashed = None;
+                }
+            }
+        }
+
+        if window.is_key_pressed(PAUSE_KEY, KeyRepeat::No) {
+            scheduler.toggle_pause();
+        }
+        if window.is_key_pressed(FRAME_ADVANCE_KEY, KeyRepeat::Yes) {
+            scheduler.advance_frame();
+        }
+        if window.is_key_pressed(INSTRUCTION_ADVANCE_KEY, KeyRepeat::Yes) {
+            scheduler.advance_instruction();
+        }
+        if window.is_key_pressed(SLOWER_KEY, KeyRepeat::Yes) {
+            scheduler.adjust_instructions_per_frame(-1);
+        }
+        if window.is_key_pressed(FASTER_KEY, KeyRepeat::Yes) {
+            scheduler.adjust_instructions_per_frame(1);
+        }
+        if window.is_key_pressed(STATUS_KEY, KeyRepeat::No) {
+            show_status = !show_status;
+        }
+        scheduler.set_turbo(window.is_key_down(TURBO_KEY));
+
+        if let Some(commands) = &commands {
+            while let Ok(command) = commands.try_recv() {
+                print!("{}", debugger.execute(&command, &mut chip8));
+                print_prompt();
+            }
+        }
+
+        // After a stall, e.g. the window being dragged, only a few of the frames owed are caught up
+        let max_behind = FRAME_DURATION * MAX_CATCH_UP_FRAMES;
+        if Instant::now() - last_frame_time > max_behind {
+            last_frame_time = Instant::now() - max_behind;
+        }
+        if Instant::now() - last_frame_time >= FRAME_DURATION {
+            let tick = scheduler.tick(Instant::now());
+            if window.is_key_down(REWIND_KEY) {
+                // Rewinding is also a way out of a crash
+                if rewind.rewind(&mut chip8) {
+                    crashed = None;
+                }
+            }
+            else if let Some(stub) = &mut gdb {
+                match stub.poll(&mut chip8) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        println!("GDB detached, running freely");
+                        gdb = None;
+                    }
+                    Err(e) => {
+                        eprintln!("GDB connection failed: {}", e);
+                        gdb = None;
+                    }
+                }
+            }
+            else if crashed.is_none() {
+                let outcome = match tick {
+                    Tick::Instruction => chip8.step_instruction().map(|_| None),
+                    Tick::Frames(frames) => run_frames(&mut debugger, &mut chip8, &mut rewind, frames, scheduler.instructions_per_frame()),
+                };
+                // Keep the window open on the last frame so the crash can be inspected
+                match outcome {
+                    Err(e) => {
+                        eprintln!("CHIP-8 crashed: {}", e);
+                        window.set_title(&format!("Chip8 Emulator - crashed: {}", e));
+                        crashed = Some(e);
+                    }
+                    Ok(stop) => {
+                        if let Some(stop) = stop {
+                            match stop {
+                                StopReason::Breakpoint(_) => println!("\nBreakpoint at {}", debugger.describe_pc(&chip8)),
+                                StopReason::Reached(_) => println!("\nStopped at {}", debugger.describe_pc(&chip8)),
+                                StopReason::Watchpoint { pc, hit } => {
+                                    println!("\nWatchpoint: {} at {}", hit, debugger.describe_address(&chip8, pc));
+                                }
+                            }
+                            print_prompt();
+                        }
+                        if chip8.is_waiting_for_key() != waiting_for_key {
+                            waiting_for_key = chip8.is_waiting_for_key();
+                            window.set_title(if waiting_for_key { "Chip8 Emulator - waiting for key" } else { "Chip8 Emulator" });
+                        }
+                    }
+                }
+
+                if chip8.has_exited() {
+                    break;
+                }
+            }
+            last_frame_time += FRAME_DURATION;
+        }
+
+        if Instant::now() - last_display_time > Duration::from_millis(10) {
+            let chip8_buffer = chip8.get_display_buffer();
+            let chip8_width = chip8.get_display_width();
+            let chip8_height = chip8_buffer.len() / chip8_width;
+
+            // An odd scale doesn't divide evenly into high resolution pixels, so scale proportionally
+            for y in 0..height {
+                let row = y * chip8_height / height * chip8_width;
+                let offset = y * width;
+                for x in 0..width {
+                    buffer[offset + x] = args.palette[chip8_buffer[row + x * chip8_width / width] as usize];
+                }
+            }
+            if show_status {
+                let status_scale = (args.scale / STATUS_SCALE_DIVISOR).max(1);
+                draw_text(&mut buffer, width, &scheduler.status(), status_scale, args.palette[1], args.palette[0]);
+            }
+            window.update_with_buffer(&buffer, width, height)
+                .map_err(|e| format!("unable to update the window: {}", e))?;
+
+            last_display_time = Instant::now();
+        }
+    }
+
+    finish_trace(&mut chip8)?;
+    match crashed {
+        Some(e) => Err(format!("CHIP-8 crashed: {}", e).into()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,86 @@
+//! Runs the ROMs bundled in `data/` and compares the final screen against the text
+//! dumps in `tests/golden/`. After an intended change to the output, regenerate
+//! them with `UPDATE_GOLDEN=1 cargo test --test golden` and review the diff.
+
+extern crate chip8;
+
+use chip8::headless::{display_to_text, HeadlessRunner, KeyScript, RunLimit};
+use chip8::{Chip8, Quirks};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Every run uses the same seed so CXKK draws the same values
+const GOLDEN_SEED: u64 = 0xC8;
+
+const INSTRUCTIONS_PER_FRAME: usize = 8;
+
+fn check_golden(name: &str, rom: &str, quirks: Quirks, frames: u64, keys: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let data = fs::read(root.join("data").join(rom)).unwrap();
+    let mut chip8 = Chip8::builder().quirks(quirks).seed(GOLDEN_SEED).rom(&data).build();
+    let mut runner = HeadlessRunner::new(RunLimit::Frames(frames), INSTRUCTIONS_PER_FRAME);
+    runner.script = KeyScript::parse(keys).unwrap();
+
+    runner.run(&mut chip8).unwrap();
+    let screen = display_to_text(&chip8);
+
+    let golden_path = root.join("tests").join("golden").join(format!("{}.txt", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden_path, &screen).unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(&golden_path)
+        .unwrap_or_else(|e| panic!("Unable to read {}: {}, run with UPDATE_GOLDEN=1 to create it", golden_path.display(), e));
+    assert!(screen == golden, "Screen of {} after {} frames differs from {}:\n{}", rom, frames, golden_path.display(), screen);
+}
+
+#[test]
+fn test_ibm_logo() {
+    check_golden("ibm_logo", "IBM Logo.ch8", Quirks::cosmac_vip(), 60, "");
+}
+
+#[test]
+fn test_opcode() {
+    check_golden("test_opcode", "test_opcode.ch8", Quirks::cosmac_vip(), 60, "");
+}
+
+#[test]
+fn test_chip8_emulator_logo() {
+    check_golden("chip8_emulator_logo", "Chip8 emulator Logo.ch8", Quirks::cosmac_vip(), 120, "");
+}
+
+#[test]
+fn test_pong() {
+    check_golden("pong", "PONG.ch8", Quirks::cosmac_vip(), 300, "");
+}
+
+#[test]
+fn test_pong_paddle_moves_up() {
+    check_golden("pong_paddle_up", "PONG.ch8", Quirks::cosmac_vip(), 160, "10+1 130-1");
+}
+
+#[test]
+fn test_pong2() {
+    check_golden("pong2", "PONG2.ch8", Quirks::cosmac_vip(), 300, "");
+}
+
+#[test]
+fn test_blinky() {
+    check_golden("blinky", "BLINKY.ch8", Quirks::chip48(), 1200, "");
+}
+
+#[test]
+fn test_guess() {
+    check_golden("guess", "GUESS.ch8", Quirks::cosmac_vip(), 120, "");
+}
+
+#[test]
+fn test_tictac() {
+    check_golden("tictac", "TICTAC.ch8", Quirks::cosmac_vip(), 120, "60+5 70-5");
+}
+
+#[test]
+fn test_ufo() {
+    check_golden("ufo", "UFO.ch8", Quirks::cosmac_vip(), 300, "100+5 110-5");
+}